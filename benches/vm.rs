@@ -0,0 +1,109 @@
+use std::{collections::HashMap, time::Instant};
+
+use prolog_interpreter::{
+    interpreter::DB,
+    lexer,
+    parser::{Item, Parser, Term},
+    vm::Machine,
+};
+
+const NREV: &str = "
+app([], ?l, ?l).
+app([?h|?t], ?l, [?h|?r]) :- app(?t, ?l, ?r).
+nrev([], []).
+nrev([?h|?t], ?r) :- nrev(?t, ?rt), app(?rt, [?h], ?r).
+";
+
+const QUEENS: &str = "
+select(?x, [?x|?t], ?t).
+select(?x, [?h|?t], [?h|?r]) :- select(?x, ?t, ?r).
+neq(?x, ?y) :- ?x < ?y.
+neq(?x, ?y) :- ?x > ?y.
+no_attack(?q, [], ?d).
+no_attack(?q, [?y|?ys], ?d) :- add(?y, ?d, ?a), neq(?q, ?a), add(?q, ?d, ?b), neq(?y, ?b), add(?d, 1, ?d1), no_attack(?q, ?ys, ?d1).
+queens([], ?qs, ?qs).
+queens(?unplaced, ?safe, ?qs) :- select(?q, ?unplaced, ?rest), no_attack(?q, ?safe, 1), queens(?rest, [?q|?safe], ?qs).
+";
+
+fn parse(source: &str) -> Vec<Item> {
+    let tokens = lexer::tokenize(source.to_string()).unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn parse_query(source: &str) -> Term {
+    let tokens = lexer::tokenize(source.to_string()).unwrap();
+    Parser::new(tokens).parse_query().unwrap()
+}
+
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut run: F) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        run();
+    }
+    println!("{:<24} {:>12.2?}/iter", name, start.elapsed() / iterations);
+}
+
+fn lookup() {
+    let facts: String = (0..500)
+        .map(|n| format!("edge({}, {}).\n", n, n + 1))
+        .collect();
+    let query = &parse_query("edge(400, ?y).");
+
+    let db = DB::new(parse(&facts));
+    bench("lookup interpreter", 200, || {
+        assert_eq!(
+            db.query(query, &mut HashMap::new()).unwrap().unwrap()["y"],
+            Term::Int(401)
+        );
+    });
+
+    let mut machine = Machine::new(parse(&facts)).unwrap();
+    bench("lookup vm", 200, || {
+        assert_eq!(machine.query(query).unwrap().unwrap()["y"], Term::Int(401));
+    });
+}
+
+fn nrev() {
+    let list: Vec<String> = (0..30).map(|n| n.to_string()).collect();
+    let query = &parse_query(&format!("nrev([{}], ?r).", list.join(", ")));
+    let reversed = Term::List((0..30).rev().map(Term::Int).collect());
+
+    let db = DB::new(parse(NREV));
+    bench("nrev30 interpreter", 100, || {
+        assert_eq!(
+            db.query(query, &mut HashMap::new()).unwrap().unwrap()["r"],
+            reversed
+        );
+    });
+
+    let mut machine = Machine::new(parse(NREV)).unwrap();
+    bench("nrev30 vm", 1000, || {
+        assert_eq!(machine.query(query).unwrap().unwrap()["r"], reversed);
+    });
+}
+
+fn queens() {
+    let mut source = QUEENS.to_string();
+    for a in 0..=16 {
+        for b in 0..=16 {
+            source += &format!("add({}, {}, {}).\n", a, b, a + b);
+        }
+    }
+    let query = &parse_query("queens([1, 2, 3, 4, 5, 6], [], ?qs).");
+
+    let db = DB::new(parse(&source));
+    bench("queens6 interpreter", 2, || {
+        assert_eq!(db.query_all(query).unwrap().len(), 4);
+    });
+
+    let mut machine = Machine::new(parse(&source)).unwrap();
+    bench("queens6 vm", 20, || {
+        assert_eq!(machine.query_all(query).unwrap().len(), 4);
+    });
+}
+
+fn main() {
+    lookup();
+    nrev();
+    queens();
+}
@@ -0,0 +1,826 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::Error,
+    parser::{Clause, Term},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Functor {
+    pub name: usize,
+    pub arity: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Const {
+    Atom(usize),
+    Str(usize),
+    Int(usize),
+    Nil,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reg {
+    X(usize),
+    Y(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Unify,
+    GreaterThan,
+    LessThan,
+    GreaterThanEqual,
+    LessThanEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    PutVariable(Reg, usize),
+    PutValue(Reg, usize),
+    PutStructure(Functor, usize),
+    PutList(usize),
+    PutConstant(Const, usize),
+    GetVariable(Reg, usize),
+    GetValue(Reg, usize),
+    GetStructure(Functor, usize),
+    GetList(usize),
+    GetConstant(Const, usize),
+    SetVariable(Reg),
+    SetValue(Reg),
+    SetConstant(Const),
+    UnifyVariable(Reg),
+    UnifyValue(Reg),
+    UnifyConstant(Const),
+    Allocate(usize),
+    Deallocate,
+    Call(Functor),
+    Execute(Functor),
+    Proceed,
+    TryMeElse(usize),
+    RetryMeElse(usize),
+    TrustMe,
+    GetLevel(Reg),
+    Cut(Reg),
+    Try(usize),
+    Retry(usize),
+    Trust(usize),
+    SwitchOnTerm {
+        var: usize,
+        constant: Option<usize>,
+        list: Option<usize>,
+        structure: Option<usize>,
+    },
+    SwitchOnConstant(usize, Option<usize>),
+    SwitchOnStructure(usize, Option<usize>),
+    CallBuiltin(Builtin),
+    Fail,
+    Halt,
+}
+
+#[derive(Debug, Default)]
+pub struct Atoms {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+
+impl Atoms {
+    pub fn intern(&mut self, name: &str) -> usize {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        self.names.push(name.to_owned());
+        self.ids.insert(name.to_owned(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub fn name(&self, id: usize) -> &str {
+        &self.names[id]
+    }
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub code: Vec<Instr>,
+    pub procedures: HashMap<Functor, usize>,
+    pub atoms: Atoms,
+    pub num_registers: usize,
+    pub constant_tables: Vec<HashMap<Const, usize>>,
+    pub structure_tables: Vec<HashMap<Functor, usize>>,
+    next_aux: usize,
+    mark: Mark,
+}
+
+#[derive(Debug, Default)]
+struct Mark {
+    code: usize,
+    constant_tables: usize,
+    structure_tables: usize,
+    next_aux: usize,
+    procedures: Vec<Functor>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Var,
+    Const(Const),
+    List,
+    Struct(Functor),
+}
+
+pub struct CompiledQuery {
+    pub start: usize,
+    pub vars: Vec<(String, usize)>,
+}
+
+impl Program {
+    pub fn compile(clauses: &[Clause]) -> Result<Program, Error> {
+        let mut program = Program {
+            code: vec![],
+            procedures: HashMap::new(),
+            atoms: Atoms::default(),
+            num_registers: 0,
+            constant_tables: vec![],
+            structure_tables: vec![],
+            next_aux: 0,
+            mark: Mark::default(),
+        };
+
+        let mut expanded = vec![];
+        for clause in clauses {
+            let mut aux = vec![];
+            let body = program.expand(&clause.body, &mut aux);
+            expanded.push(Clause {
+                head: clause.head.clone(),
+                body,
+            });
+            expanded.extend(aux);
+        }
+
+        let grouped = program.group(&expanded)?;
+        for (functor, _) in grouped.iter() {
+            program.procedures.insert(*functor, 0);
+        }
+        for (functor, clauses) in grouped {
+            program.compile_procedure(functor, &clauses)?;
+        }
+
+        program.mark = Mark {
+            code: program.code.len(),
+            constant_tables: program.constant_tables.len(),
+            structure_tables: program.structure_tables.len(),
+            next_aux: program.next_aux,
+            procedures: vec![],
+        };
+        Ok(program)
+    }
+
+    fn group<'c>(
+        &mut self,
+        clauses: &'c [Clause],
+    ) -> Result<Vec<(Functor, Vec<&'c Clause>)>, Error> {
+        let mut grouped: Vec<(Functor, Vec<&Clause>)> = vec![];
+        for clause in clauses {
+            let (name, arity) = clause.key()?;
+            let functor = Functor {
+                name: self.atoms.intern(&name),
+                arity,
+            };
+            match grouped.iter_mut().find(|(other, _)| *other == functor) {
+                Some((_, clauses)) => clauses.push(clause),
+                None => grouped.push((functor, vec![clause])),
+            }
+        }
+        Ok(grouped)
+    }
+
+    fn compile_procedure(&mut self, functor: Functor, clauses: &[&Clause]) -> Result<(), Error> {
+        let chain = self.code.len();
+        self.procedures.insert(functor, chain);
+        let mut retry_at: Option<usize> = None;
+        let mut starts = vec![];
+
+        for (idx, clause) in clauses.iter().enumerate() {
+            if let Some(at) = retry_at {
+                let here = self.code.len();
+                match &mut self.code[at] {
+                    Instr::TryMeElse(label) | Instr::RetryMeElse(label) => *label = here,
+                    _ => unreachable!(),
+                }
+            }
+            if clauses.len() > 1 {
+                retry_at = Some(self.code.len());
+                if idx == 0 {
+                    self.code.push(Instr::TryMeElse(0));
+                } else if idx == clauses.len() - 1 {
+                    self.code.push(Instr::TrustMe);
+                } else {
+                    self.code.push(Instr::RetryMeElse(0));
+                }
+            }
+            starts.push(self.code.len());
+            self.compile_clause(clause)?;
+        }
+
+        if clauses.len() > 1 && functor.arity > 0 {
+            let keys: Vec<Key> = clauses.iter().map(|clause| self.key(clause)).collect();
+            if keys.iter().any(|key| *key != Key::Var) {
+                let entry = self.index(chain, &starts, &keys);
+                self.procedures.insert(functor, entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn expand(&mut self, body: &Term, aux: &mut Vec<Clause>) -> Term {
+        let Term::CompoundTerm(name, args) = body else {
+            return body.clone();
+        };
+        match (name.as_str(), args.as_slice()) {
+            (",", [left, right]) => conjunction(self.expand(left, aux), self.expand(right, aux)),
+            ("!", []) => cut(LEVEL),
+            (";", [Term::CompoundTerm(arrow, branch), otherwise])
+                if arrow == "->" && branch.len() == 2 =>
+            {
+                let then = self.if_then(&branch[0], &branch[1], aux);
+                let otherwise = self.expand(otherwise, aux);
+                self.auxiliary(vec![then, otherwise], aux)
+            }
+            (";", [left, right]) => {
+                let left = self.expand(left, aux);
+                let right = self.expand(right, aux);
+                self.auxiliary(vec![left, right], aux)
+            }
+            ("->", [condition, then]) => {
+                let then = self.if_then(condition, then, aux);
+                self.auxiliary(vec![then], aux)
+            }
+            ("\\+" | "not", [goal]) => {
+                let goal = self.expand(goal, aux);
+                let fail = Term::CompoundTerm("fail".to_string(), vec![]);
+                let negated = conjunction(goal, conjunction(cut(LOCAL), fail));
+                let succeed = Term::CompoundTerm("true".to_string(), vec![]);
+                self.auxiliary(vec![negated, succeed], aux)
+            }
+            _ => body.clone(),
+        }
+    }
+
+    fn if_then(&mut self, condition: &Term, then: &Term, aux: &mut Vec<Clause>) -> Term {
+        let condition = self.expand(condition, aux);
+        let then = self.expand(then, aux);
+        conjunction(condition, conjunction(cut(LOCAL), then))
+    }
+
+    fn auxiliary(&mut self, branches: Vec<Term>, aux: &mut Vec<Clause>) -> Term {
+        let mut vars = vec![];
+        for branch in branches.iter() {
+            branch.collect_vars(&mut vars);
+        }
+        vars.retain(|name| name != LOCAL);
+        let name = format!("$aux{}", self.next_aux);
+        self.next_aux += 1;
+
+        let args: Vec<Term> = vars.into_iter().map(Term::Variable).collect();
+        let call = Term::CompoundTerm(name.clone(), args);
+        let head = rename(&rename(&call, LEVEL, OUTER), LOCAL, LEVEL);
+        for branch in branches {
+            aux.push(Clause {
+                head: head.clone(),
+                body: rename(&rename(&branch, LEVEL, OUTER), LOCAL, LEVEL),
+            });
+        }
+        call
+    }
+
+    fn key(&mut self, clause: &Clause) -> Key {
+        let Term::CompoundTerm(_, args) = &clause.head else {
+            unreachable!()
+        };
+        match &args[0] {
+            Term::Variable(_) => Key::Var,
+            Term::CompoundTerm(name, args) if !args.is_empty() => Key::Struct(Functor {
+                name: self.atoms.intern(name),
+                arity: args.len(),
+            }),
+            arg => match self.constant(arg) {
+                Some(constant) => Key::Const(constant),
+                None => Key::List,
+            },
+        }
+    }
+
+    fn index(&mut self, chain: usize, starts: &[usize], keys: &[Key]) -> usize {
+        let matching = |program: &mut Program, wanted: &dyn Fn(&Key) -> bool| {
+            let clauses: Vec<usize> = starts
+                .iter()
+                .zip(keys)
+                .filter(|(_, key)| **key == Key::Var || wanted(key))
+                .map(|(start, _)| *start)
+                .collect();
+            if clauses.len() == starts.len() {
+                Some(chain)
+            } else {
+                program.chain(&clauses)
+            }
+        };
+
+        let default = matching(self, &|_| false);
+        let list = matching(self, &|key| *key == Key::List);
+        let mut constants = HashMap::new();
+        let mut structures = HashMap::new();
+        for key in keys {
+            match key {
+                Key::Const(constant) if !constants.contains_key(constant) => {
+                    let label = matching(self, &|other| other == key).unwrap();
+                    constants.insert(*constant, label);
+                }
+                Key::Struct(functor) if !structures.contains_key(functor) => {
+                    let label = matching(self, &|other| other == key).unwrap();
+                    structures.insert(*functor, label);
+                }
+                _ => (),
+            }
+        }
+
+        let constant = if constants.is_empty() {
+            default
+        } else {
+            self.constant_tables.push(constants);
+            let table = self.constant_tables.len() - 1;
+            self.code.push(Instr::SwitchOnConstant(table, default));
+            Some(self.code.len() - 1)
+        };
+        let structure = if structures.is_empty() {
+            default
+        } else {
+            self.structure_tables.push(structures);
+            let table = self.structure_tables.len() - 1;
+            self.code.push(Instr::SwitchOnStructure(table, default));
+            Some(self.code.len() - 1)
+        };
+        self.code.push(Instr::SwitchOnTerm {
+            var: chain,
+            constant,
+            list,
+            structure,
+        });
+        self.code.len() - 1
+    }
+
+    fn chain(&mut self, clauses: &[usize]) -> Option<usize> {
+        match clauses {
+            [] => None,
+            [clause] => Some(*clause),
+            [first, middle @ .., last] => {
+                let start = self.code.len();
+                self.code.push(Instr::Try(*first));
+                self.code
+                    .extend(middle.iter().map(|clause| Instr::Retry(*clause)));
+                self.code.push(Instr::Trust(*last));
+                Some(start)
+            }
+        }
+    }
+
+    fn constant(&mut self, value: &Term) -> Option<Const> {
+        match value {
+            Term::Int(val) => Some(Const::Int(*val)),
+            Term::Str(val) => Some(Const::Str(self.atoms.intern(val))),
+            Term::List(values) if values.is_empty() => Some(Const::Nil),
+            Term::CompoundTerm(name, args) if args.is_empty() => {
+                Some(Const::Atom(self.atoms.intern(name)))
+            }
+            _ => None,
+        }
+    }
+
+    fn reset_queries(&mut self) {
+        self.code.truncate(self.mark.code);
+        self.constant_tables.truncate(self.mark.constant_tables);
+        self.structure_tables.truncate(self.mark.structure_tables);
+        self.next_aux = self.mark.next_aux;
+        for functor in self.mark.procedures.drain(..) {
+            self.procedures.remove(&functor);
+        }
+    }
+
+    pub fn compile_query(&mut self, query: &Term) -> Result<CompiledQuery, Error> {
+        self.reset_queries();
+        let mut aux = vec![];
+        let query = self.expand(query, &mut aux);
+        let grouped = self.group(&aux)?;
+        for (functor, _) in grouped.iter() {
+            self.procedures.insert(*functor, 0);
+            self.mark.procedures.push(*functor);
+        }
+        for (functor, clauses) in grouped {
+            self.compile_procedure(functor, &clauses)?;
+        }
+
+        let start = self.code.len();
+        let goals = body_goals(&query);
+        let mut names: Vec<String> = vec![];
+        for goal in goals.iter() {
+            goal.collect_vars(&mut names);
+        }
+        let permanent: HashMap<String, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (name.clone(), idx))
+            .collect();
+
+        let mut compiler = ClauseCompiler::new(self, permanent.clone(), max_arity(&[], &goals));
+        compiler.emit(Instr::Allocate(names.len()));
+        if let Some(idx) = permanent.get(LEVEL) {
+            compiler.emit(Instr::GetLevel(Reg::Y(*idx)));
+        }
+        for goal in goals.iter() {
+            compiler.compile_goal(goal, false)?;
+        }
+        compiler.emit(Instr::Halt);
+        compiler.finish();
+
+        Ok(CompiledQuery {
+            start,
+            vars: names
+                .into_iter()
+                .filter(|name| !name.starts_with('$'))
+                .map(|name| {
+                    let idx = permanent[&name];
+                    (name, idx)
+                })
+                .collect(),
+        })
+    }
+
+    fn compile_clause(&mut self, clause: &Clause) -> Result<(), Error> {
+        let Term::CompoundTerm(_, head_args) = &clause.head else {
+            unreachable!()
+        };
+        let goals = if clause.is_fact() {
+            vec![]
+        } else {
+            body_goals(&clause.body)
+        };
+
+        let mut chunks: Vec<Vec<String>> = vec![];
+        let mut head_vars: Vec<String> = vec![];
+        for arg in head_args {
+            arg.collect_vars(&mut head_vars);
+        }
+        let mut body_vars = vec![];
+        clause.body.collect_vars(&mut body_vars);
+        let level = body_vars.iter().any(|name| name == LEVEL);
+        if level {
+            head_vars.push(LEVEL.to_string());
+        }
+        if goals.is_empty() {
+            chunks.push(head_vars);
+        } else {
+            for (idx, goal) in goals.iter().enumerate() {
+                let mut vars = if idx == 0 { head_vars.clone() } else { vec![] };
+                goal.collect_vars(&mut vars);
+                chunks.push(vars);
+            }
+        }
+
+        let mut seen_in: HashMap<String, usize> = HashMap::new();
+        let mut permanent: HashMap<String, usize> = HashMap::new();
+        for (idx, chunk) in chunks.iter().enumerate() {
+            for name in chunk {
+                match seen_in.get(name) {
+                    Some(first) if *first != idx => {
+                        if !permanent.contains_key(name) {
+                            permanent.insert(name.clone(), permanent.len());
+                        }
+                    }
+                    Some(_) => (),
+                    None => {
+                        seen_in.insert(name.clone(), idx);
+                    }
+                }
+            }
+        }
+
+        let needs_env = goals.len() > 1;
+        let mut compiler = ClauseCompiler::new(self, permanent, max_arity(head_args, &goals));
+        if needs_env {
+            let size = compiler.permanent.len();
+            compiler.emit(Instr::Allocate(size));
+        }
+        if level {
+            let (reg, _) = compiler.register(LEVEL);
+            compiler.emit(Instr::GetLevel(reg));
+        }
+        compiler.compile_head(head_args)?;
+
+        if goals.is_empty() {
+            compiler.emit(Instr::Proceed);
+        }
+        for (idx, goal) in goals.iter().enumerate() {
+            compiler.compile_goal(goal, idx == goals.len() - 1 && !needs_env)?;
+            if idx == goals.len() - 1 && needs_env {
+                match compiler.program.code.pop() {
+                    Some(Instr::Call(functor)) => {
+                        compiler.emit(Instr::Deallocate);
+                        compiler.emit(Instr::Execute(functor));
+                    }
+                    Some(instr) => {
+                        compiler.emit(instr);
+                        compiler.emit(Instr::Deallocate);
+                        compiler.emit(Instr::Proceed);
+                    }
+                    None => unreachable!(),
+                }
+            }
+        }
+        compiler.finish();
+        Ok(())
+    }
+}
+
+struct ClauseCompiler<'a> {
+    program: &'a mut Program,
+    permanent: HashMap<String, usize>,
+    temporary: HashMap<String, usize>,
+    seen: HashSet<String>,
+    next_register: usize,
+}
+
+impl<'a> ClauseCompiler<'a> {
+    fn new(program: &'a mut Program, permanent: HashMap<String, usize>, arity: usize) -> Self {
+        ClauseCompiler {
+            program,
+            permanent,
+            temporary: HashMap::new(),
+            seen: HashSet::new(),
+            next_register: arity,
+        }
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        self.program.code.push(instr);
+    }
+
+    fn finish(self) {
+        self.program.num_registers = self.program.num_registers.max(self.next_register);
+    }
+
+    fn fresh_register(&mut self) -> usize {
+        self.next_register += 1;
+        self.next_register - 1
+    }
+
+    fn register(&mut self, name: &str) -> (Reg, bool) {
+        let first = self.seen.insert(name.to_owned());
+        if let Some(idx) = self.permanent.get(name) {
+            return (Reg::Y(*idx), first);
+        }
+        if let Some(idx) = self.temporary.get(name) {
+            return (Reg::X(*idx), first);
+        }
+        let idx = self.fresh_register();
+        self.temporary.insert(name.to_owned(), idx);
+        (Reg::X(idx), first)
+    }
+
+    fn constant(&mut self, value: &Term) -> Option<Const> {
+        self.program.constant(value)
+    }
+
+    fn functor(&mut self, name: &str, arity: usize) -> Functor {
+        Functor {
+            name: self.program.atoms.intern(name),
+            arity,
+        }
+    }
+
+    fn compile_head(&mut self, args: &[Term]) -> Result<(), Error> {
+        let mut pending: Vec<(usize, Term)> = vec![];
+        for (idx, arg) in args.iter().enumerate() {
+            if let Term::Variable(name) = arg {
+                let (reg, first) = self.register(name);
+                if first {
+                    self.emit(Instr::GetVariable(reg, idx));
+                } else {
+                    self.emit(Instr::GetValue(reg, idx));
+                }
+            } else if let Some(constant) = self.constant(arg) {
+                self.emit(Instr::GetConstant(constant, idx));
+            } else {
+                pending.push((idx, arg.clone()));
+            }
+
+            while let Some((register, term)) = pending.pop() {
+                let args = match &term {
+                    Term::CompoundTerm(name, args) => {
+                        let functor = self.functor(name, args.len());
+                        self.emit(Instr::GetStructure(functor, register));
+                        args.clone()
+                    }
+                    _ => {
+                        self.emit(Instr::GetList(register));
+                        let (head, tail) = split_list(&term)?;
+                        vec![head, tail]
+                    }
+                };
+                for arg in args.iter() {
+                    self.unify_arg(arg, &mut pending);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn unify_arg(&mut self, arg: &Term, pending: &mut Vec<(usize, Term)>) {
+        if let Term::Variable(name) = arg {
+            let (reg, first) = self.register(name);
+            if first {
+                self.emit(Instr::UnifyVariable(reg));
+            } else {
+                self.emit(Instr::UnifyValue(reg));
+            }
+        } else if let Some(constant) = self.constant(arg) {
+            self.emit(Instr::UnifyConstant(constant));
+        } else {
+            let register = self.fresh_register();
+            self.emit(Instr::UnifyVariable(Reg::X(register)));
+            pending.push((register, arg.clone()));
+        }
+    }
+
+    fn compile_goal(&mut self, goal: &Term, last: bool) -> Result<(), Error> {
+        let (name, args) = match goal {
+            Term::CompoundTerm(name, args) => (name, args),
+            Term::Variable(_) => return Err(Error::Instantiation),
+            _ => return Err(Error::Type("callable".to_string(), goal.clone())),
+        };
+        match (name.as_str(), args.as_slice()) {
+            (_, [left, right]) if builtin(name).is_some() => {
+                self.put_arg(left, 0)?;
+                self.put_arg(right, 1)?;
+                self.emit(Instr::CallBuiltin(builtin(name).unwrap()));
+            }
+            ("true", []) => (),
+            ("fail" | "false", []) => self.emit(Instr::Fail),
+            ("$cut", [Term::Variable(level)]) => {
+                let (reg, _) = self.register(level);
+                self.emit(Instr::Cut(reg));
+            }
+            _ => {
+                let functor = self.functor(name, args.len());
+                if !self.program.procedures.contains_key(&functor) {
+                    return Err(Error::Existence(name.clone(), args.len()));
+                }
+                for (idx, arg) in args.iter().enumerate() {
+                    self.put_arg(arg, idx)?;
+                }
+                if last {
+                    self.emit(Instr::Execute(functor));
+                } else {
+                    self.emit(Instr::Call(functor));
+                }
+                return Ok(());
+            }
+        }
+        if last {
+            self.emit(Instr::Proceed);
+        }
+        Ok(())
+    }
+
+    fn put_arg(&mut self, arg: &Term, idx: usize) -> Result<(), Error> {
+        if let Term::Variable(name) = arg {
+            let (reg, first) = self.register(name);
+            if first {
+                self.emit(Instr::PutVariable(reg, idx));
+            } else {
+                self.emit(Instr::PutValue(reg, idx));
+            }
+        } else if let Some(constant) = self.constant(arg) {
+            self.emit(Instr::PutConstant(constant, idx));
+        } else {
+            self.build(arg, idx)?;
+        }
+        Ok(())
+    }
+
+    fn build(&mut self, term: &Term, register: usize) -> Result<(), Error> {
+        let args = match term {
+            Term::CompoundTerm(_, args) => args.clone(),
+            _ => {
+                let (head, tail) = split_list(term)?;
+                vec![head, tail]
+            }
+        };
+
+        let mut nested: Vec<Option<usize>> = vec![];
+        for arg in args.iter() {
+            nested.push(match arg {
+                Term::Variable(_) => None,
+                _ if self.constant(arg).is_some() => None,
+                _ => {
+                    let nested = self.fresh_register();
+                    self.build(arg, nested)?;
+                    Some(nested)
+                }
+            });
+        }
+
+        match term {
+            Term::CompoundTerm(name, args) => {
+                let functor = self.functor(name, args.len());
+                self.emit(Instr::PutStructure(functor, register));
+            }
+            _ => self.emit(Instr::PutList(register)),
+        }
+
+        for (arg, nested) in args.iter().zip(nested) {
+            if let Some(nested) = nested {
+                self.emit(Instr::SetValue(Reg::X(nested)));
+            } else if let Term::Variable(name) = arg {
+                let (reg, first) = self.register(name);
+                if first {
+                    self.emit(Instr::SetVariable(reg));
+                } else {
+                    self.emit(Instr::SetValue(reg));
+                }
+            } else {
+                let constant = self.constant(arg).unwrap();
+                self.emit(Instr::SetConstant(constant));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn split_list(term: &Term) -> Result<(Term, Term), Error> {
+    match term {
+        Term::List(values) if !values.is_empty() => {
+            Ok((values[0].clone(), Term::List(values[1..].to_vec())))
+        }
+        Term::Cons(head, tail) => Ok((head.as_ref().clone(), tail.as_ref().clone())),
+        _ => Err(Error::Type("list".to_string(), term.clone())),
+    }
+}
+
+const LEVEL: &str = "$level";
+const LOCAL: &str = "$local";
+const OUTER: &str = "$outer";
+
+fn cut(level: &str) -> Term {
+    Term::CompoundTerm("$cut".to_string(), vec![Term::Variable(level.to_string())])
+}
+
+fn conjunction(left: Term, right: Term) -> Term {
+    Term::CompoundTerm(",".to_string(), vec![left, right])
+}
+
+fn rename(term: &Term, from: &str, to: &str) -> Term {
+    match term {
+        Term::Variable(name) if name == from => Term::Variable(to.to_string()),
+        Term::CompoundTerm(name, args) => Term::CompoundTerm(
+            name.clone(),
+            args.iter().map(|arg| rename(arg, from, to)).collect(),
+        ),
+        Term::List(values) => Term::List(values.iter().map(|val| rename(val, from, to)).collect()),
+        Term::Cons(head, tail) => Term::Cons(
+            Box::new(rename(head, from, to)),
+            Box::new(rename(tail, from, to)),
+        ),
+        Term::Variable(_) | Term::Str(_) | Term::Int(_) => term.clone(),
+    }
+}
+
+fn builtin(name: &str) -> Option<Builtin> {
+    match name {
+        "=" => Some(Builtin::Unify),
+        ">" => Some(Builtin::GreaterThan),
+        "<" => Some(Builtin::LessThan),
+        ">=" => Some(Builtin::GreaterThanEqual),
+        "<=" => Some(Builtin::LessThanEqual),
+        _ => None,
+    }
+}
+
+fn body_goals(body: &Term) -> Vec<Term> {
+    match body {
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => {
+            let mut goals = body_goals(&args[0]);
+            goals.extend(body_goals(&args[1]));
+            goals
+        }
+        _ => vec![body.clone()],
+    }
+}
+
+fn max_arity(head_args: &[Term], goals: &[Term]) -> usize {
+    goals
+        .iter()
+        .map(|goal| match goal {
+            Term::CompoundTerm(_, args) => args.len(),
+            _ => 2,
+        })
+        .fold(head_args.len(), usize::max)
+}
@@ -0,0 +1,1476 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{Item, Term};
+
+type Tuple = Vec<Term>;
+type Predicate = (String, usize);
+type Index = HashMap<Tuple, Vec<usize>>;
+type Indexes = HashMap<(Predicate, Vec<usize>), Index>;
+type ChangeSet = HashMap<Predicate, Changes>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotDatalog {
+    FunctionSymbol(Term),
+    UnsafeVariable(Term, String),
+    UnsupportedGoal(Term),
+    UnsupportedDirective(Term),
+    Unstratifiable(Vec<Term>),
+    Type(String, Term),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Delta {
+    pub inserted: Vec<Term>,
+    pub deleted: Vec<Term>,
+}
+
+#[derive(Debug, Default)]
+struct Relation {
+    tuples: Vec<Tuple>,
+    positions: HashMap<Tuple, usize>,
+}
+
+impl Relation {
+    fn contains(&self, tuple: &Tuple) -> bool {
+        self.positions.contains_key(tuple)
+    }
+
+    fn insert(&mut self, tuple: Tuple) -> bool {
+        if self.contains(&tuple) {
+            return false;
+        }
+        self.positions.insert(tuple.clone(), self.tuples.len());
+        self.tuples.push(tuple);
+        true
+    }
+
+    fn remove(&mut self, tuple: &Tuple) -> bool {
+        let Some(idx) = self.positions.remove(tuple) else {
+            return false;
+        };
+        self.tuples.swap_remove(idx);
+        if idx < self.tuples.len() {
+            self.positions.insert(self.tuples[idx].clone(), idx);
+        }
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct Changes {
+    inserted: Relation,
+    deleted: Relation,
+}
+
+#[derive(Debug, Default)]
+struct Touched {
+    tuples: Vec<Tuple>,
+    before: HashMap<Tuple, bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum View {
+    Old,
+    New,
+}
+
+#[derive(Clone, Copy)]
+struct Pass<'a> {
+    view: View,
+    changes: &'a ChangeSet,
+    seed: Option<(usize, &'a [Tuple])>,
+    check_seed: bool,
+}
+
+impl<'a> Pass<'a> {
+    fn new(changes: &'a ChangeSet) -> Pass<'a> {
+        Pass {
+            view: View::New,
+            changes,
+            seed: None,
+            check_seed: true,
+        }
+    }
+
+    fn seeded(self, view: View, position: usize, tuples: &'a [Tuple]) -> Pass<'a> {
+        Pass {
+            view,
+            seed: Some((position, tuples)),
+            check_seed: view == View::New,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    GreaterThan,
+    LessThan,
+    GreaterThanEqual,
+    LessThanEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone)]
+enum Goal {
+    Atom(Predicate, Vec<Term>),
+    Not(Predicate, Vec<Term>),
+    Eq(Term, Term),
+    Compare(Comparison, Term, Term),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    clause: Term,
+    head: (Predicate, Vec<Term>),
+    body: Vec<Goal>,
+}
+
+impl Rule {
+    fn has_aggregate(&self) -> bool {
+        self.head.1.iter().any(|arg| aggregate(arg).is_some())
+    }
+
+    fn dependencies(&self) -> Vec<(&Predicate, bool)> {
+        self.body
+            .iter()
+            .filter_map(|goal| match goal {
+                Goal::Atom(predicate, _) => Some((predicate, self.has_aggregate())),
+                Goal::Not(predicate, _) => Some((predicate, true)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Datalog {
+    relations: HashMap<Predicate, Relation>,
+    base: HashMap<Predicate, HashSet<Tuple>>,
+    rules: Vec<Rule>,
+    strata: Vec<Vec<usize>>,
+}
+
+impl Datalog {
+    pub fn new(program: Vec<Item>) -> Result<Datalog, NotDatalog> {
+        let mut datalog = Datalog::default();
+
+        for item in program {
+            let clause = match item {
+                Item::Directive(goal) => match &goal {
+                    Term::CompoundTerm(name, _) if name == "table" => continue,
+                    _ => return Err(NotDatalog::UnsupportedDirective(goal)),
+                },
+                Item::Clause(clause) => clause,
+                Item::Query(_) => continue,
+            };
+            if clause.is_fact() {
+                let (predicate, tuple) = fact(&clause.head)?;
+                datalog
+                    .base
+                    .entry(predicate.clone())
+                    .or_default()
+                    .insert(tuple.clone());
+                datalog
+                    .relations
+                    .entry(predicate)
+                    .or_default()
+                    .insert(tuple);
+                continue;
+            }
+
+            let term = clause.to_term();
+            let Term::CompoundTerm(name, args) = &clause.head else {
+                return Err(NotDatalog::UnsupportedGoal(term));
+            };
+            if args
+                .iter()
+                .any(|arg| !is_constant(arg) && !is_variable(arg) && aggregate(arg).is_none())
+            {
+                return Err(NotDatalog::FunctionSymbol(term));
+            }
+            let rule = Rule {
+                head: ((name.clone(), args.len()), args.clone()),
+                body: schedule(&term, args, body_goals(&term, &clause.body)?)?,
+                clause: term,
+            };
+            datalog.relations.entry(rule.head.0.clone()).or_default();
+            datalog.rules.push(rule);
+        }
+
+        datalog.strata = datalog.strata()?;
+        for stratum in datalog.strata.clone() {
+            datalog.evaluate(&stratum)?;
+        }
+        Ok(datalog)
+    }
+
+    pub fn query(&self, query: &Term) -> Result<Vec<HashMap<String, Term>>, NotDatalog> {
+        let goals = schedule(query, &[], body_goals(query, query)?)?;
+        let mut names = vec![];
+        query.collect_vars(&mut names);
+
+        let mut answers = vec![];
+        let changes = HashMap::new();
+        self.join(
+            &goals,
+            0,
+            Pass::new(&changes),
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            &mut |bindings| {
+                answers.push(
+                    names
+                        .iter()
+                        .filter_map(|name| Some((name.clone(), bindings.get(name)?.clone())))
+                        .collect(),
+                );
+            },
+        )?;
+        Ok(answers)
+    }
+
+    pub fn insert(&mut self, facts: Vec<Term>) -> Result<Delta, NotDatalog> {
+        self.update(facts, vec![])
+    }
+
+    pub fn retract(&mut self, facts: Vec<Term>) -> Result<Delta, NotDatalog> {
+        self.update(vec![], facts)
+    }
+
+    pub fn update(
+        &mut self,
+        inserted: Vec<Term>,
+        retracted: Vec<Term>,
+    ) -> Result<Delta, NotDatalog> {
+        let inserted = inserted.iter().map(fact).collect::<Result<Vec<_>, _>>()?;
+        let retracted = retracted.iter().map(fact).collect::<Result<Vec<_>, _>>()?;
+        let derived: Vec<Predicate> = self.derived_predicates();
+
+        let mut touched: HashMap<Predicate, Touched> = HashMap::new();
+        let mut removed: HashMap<Predicate, Vec<Tuple>> = HashMap::new();
+        for (predicate, tuple) in retracted {
+            if !self
+                .base
+                .get_mut(&predicate)
+                .is_some_and(|base| base.remove(&tuple))
+            {
+                continue;
+            }
+            if derived.contains(&predicate) {
+                removed.entry(predicate).or_default().push(tuple);
+            } else {
+                self.set(&mut touched, &predicate, tuple, false);
+            }
+        }
+        for (predicate, tuple) in inserted {
+            if !self
+                .base
+                .entry(predicate.clone())
+                .or_default()
+                .insert(tuple.clone())
+            {
+                continue;
+            }
+            if let Some(tuples) = removed.get_mut(&predicate) {
+                tuples.retain(|removed| *removed != tuple);
+            }
+            self.set(&mut touched, &predicate, tuple, true);
+        }
+
+        let mut changes: ChangeSet = HashMap::new();
+        for predicate in touched.keys() {
+            changes.insert(predicate.clone(), self.changes(&touched, predicate));
+        }
+
+        for stratum in self.strata.clone() {
+            let heads: HashSet<Predicate> = stratum
+                .iter()
+                .map(|idx| self.rules[*idx].head.0.clone())
+                .collect();
+            let affected = heads.iter().any(|head| removed.contains_key(head))
+                || stratum.iter().any(|idx| {
+                    let rule = &self.rules[*idx];
+                    std::iter::once(&rule.head.0)
+                        .chain(rule.dependencies().into_iter().map(|(dep, _)| dep))
+                        .any(|predicate| changes.contains_key(predicate))
+                });
+            if !affected {
+                continue;
+            }
+
+            if stratum.iter().any(|idx| self.rules[*idx].has_aggregate()) {
+                self.recompute(&stratum, &heads, &mut touched)?;
+            } else {
+                self.maintain(&stratum, &heads, &changes, &removed, &mut touched)?;
+            }
+            for head in heads {
+                if touched.contains_key(&head) {
+                    let head_changes = self.changes(&touched, &head);
+                    changes.insert(head, head_changes);
+                }
+            }
+        }
+
+        let mut delta = Delta::default();
+        for predicate in derived {
+            if let Some(Changes { inserted, deleted }) = changes.remove(&predicate) {
+                let to_value = |tuple: Tuple| Term::CompoundTerm(predicate.0.clone(), tuple);
+                delta
+                    .inserted
+                    .extend(inserted.tuples.into_iter().map(to_value));
+                delta
+                    .deleted
+                    .extend(deleted.tuples.into_iter().map(to_value));
+            }
+        }
+        Ok(delta)
+    }
+
+    fn derived_predicates(&self) -> Vec<Predicate> {
+        let mut predicates: Vec<Predicate> = vec![];
+        for rule in self.rules.iter() {
+            if !predicates.contains(&rule.head.0) {
+                predicates.push(rule.head.0.clone());
+            }
+        }
+        predicates
+    }
+
+    fn set(
+        &mut self,
+        touched: &mut HashMap<Predicate, Touched>,
+        predicate: &Predicate,
+        tuple: Tuple,
+        present: bool,
+    ) -> bool {
+        let relation = self.relations.entry(predicate.clone()).or_default();
+        let before = relation.contains(&tuple);
+        if before == present {
+            return false;
+        }
+
+        let touched = touched.entry(predicate.clone()).or_default();
+        if !touched.before.contains_key(&tuple) {
+            touched.before.insert(tuple.clone(), before);
+            touched.tuples.push(tuple.clone());
+        }
+        if present {
+            relation.insert(tuple);
+        } else {
+            relation.remove(&tuple);
+        }
+        true
+    }
+
+    fn changes(&self, touched: &HashMap<Predicate, Touched>, predicate: &Predicate) -> Changes {
+        let mut changes = Changes::default();
+        let relation = &self.relations[predicate];
+        for tuple in touched[predicate].tuples.iter() {
+            match (touched[predicate].before[tuple], relation.contains(tuple)) {
+                (false, true) => changes.inserted.insert(tuple.clone()),
+                (true, false) => changes.deleted.insert(tuple.clone()),
+                _ => false,
+            };
+        }
+        changes
+    }
+
+    fn recompute(
+        &mut self,
+        stratum: &[usize],
+        heads: &HashSet<Predicate>,
+        touched: &mut HashMap<Predicate, Touched>,
+    ) -> Result<(), NotDatalog> {
+        let mut old: Vec<(Predicate, Tuple)> = vec![];
+        for head in heads {
+            let relation = self.relations.get(head).map(|r| r.tuples.clone());
+            for tuple in relation.into_iter().flatten() {
+                old.push((head.clone(), tuple));
+            }
+        }
+        for (head, tuple) in old {
+            self.set(touched, &head, tuple, false);
+        }
+        for head in heads {
+            let base: Vec<Tuple> = self.base.get(head).into_iter().flatten().cloned().collect();
+            for tuple in base {
+                self.set(touched, head, tuple, true);
+            }
+        }
+
+        let before: HashMap<Predicate, usize> = heads
+            .iter()
+            .map(|head| (head.clone(), self.relations[head].tuples.len()))
+            .collect();
+        self.evaluate(stratum)?;
+        for head in heads {
+            let added = self.relations[head].tuples[before[head]..].to_vec();
+            let touched = touched.entry(head.clone()).or_default();
+            for tuple in added {
+                if !touched.before.contains_key(&tuple) {
+                    touched.before.insert(tuple.clone(), false);
+                    touched.tuples.push(tuple);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn maintain(
+        &mut self,
+        stratum: &[usize],
+        heads: &HashSet<Predicate>,
+        changes: &ChangeSet,
+        removed: &HashMap<Predicate, Vec<Tuple>>,
+        touched: &mut HashMap<Predicate, Touched>,
+    ) -> Result<(), NotDatalog> {
+        let rules: Vec<&Rule> = stratum.iter().map(|idx| &self.rules[*idx]).collect();
+
+        let mut over: Vec<(Predicate, Tuple)> = vec![];
+        let mut over_set: HashSet<(Predicate, Tuple)> = HashSet::new();
+        let mut frontier: HashMap<Predicate, Vec<Tuple>> = HashMap::new();
+        let mut derived: Vec<(Predicate, Tuple)> = vec![];
+        for head in heads {
+            for tuple in removed.get(head).into_iter().flatten() {
+                derived.push((head.clone(), tuple.clone()));
+            }
+        }
+
+        let mut indexes = HashMap::new();
+        for rule in rules.iter() {
+            for (idx, goal) in rule.body.iter().enumerate() {
+                let tuples = match goal {
+                    Goal::Atom(predicate, _) if !heads.contains(predicate) => changes
+                        .get(predicate)
+                        .map(|changes| &changes.deleted.tuples),
+                    Goal::Not(predicate, _) => changes
+                        .get(predicate)
+                        .map(|changes| &changes.inserted.tuples),
+                    _ => None,
+                };
+                if let Some(tuples) = tuples.filter(|tuples| !tuples.is_empty()) {
+                    let pass = Pass::new(changes).seeded(View::Old, idx, tuples);
+                    self.fire(rule, pass, &mut indexes, &mut derived)?;
+                }
+            }
+        }
+
+        loop {
+            for (predicate, tuple) in derived.drain(..) {
+                let present = self.relations[&predicate].contains(&tuple);
+                if present && over_set.insert((predicate.clone(), tuple.clone())) {
+                    over.push((predicate.clone(), tuple.clone()));
+                    frontier.entry(predicate).or_default().push(tuple);
+                }
+            }
+            if frontier.is_empty() {
+                break;
+            }
+            let mut indexes = HashMap::new();
+            for rule in rules.iter() {
+                for (idx, goal) in rule.body.iter().enumerate() {
+                    if let Goal::Atom(predicate, _) = goal {
+                        if let Some(tuples) = frontier.get(predicate) {
+                            let pass = Pass::new(changes).seeded(View::Old, idx, tuples);
+                            self.fire(rule, pass, &mut indexes, &mut derived)?;
+                        }
+                    }
+                }
+            }
+            frontier.clear();
+        }
+
+        let rules: Vec<Rule> = rules.into_iter().cloned().collect();
+        for (predicate, tuple) in over.iter() {
+            self.set(touched, predicate, tuple.clone(), false);
+        }
+
+        let mut frontier: HashMap<Predicate, Vec<Tuple>> = HashMap::new();
+        for (predicate, tuple) in over {
+            let base = self
+                .base
+                .get(&predicate)
+                .is_some_and(|base| base.contains(&tuple));
+            if base || self.derivable(&rules, &predicate, &tuple, changes)? {
+                self.set(touched, &predicate, tuple.clone(), true);
+                frontier.entry(predicate).or_default().push(tuple);
+            }
+        }
+        for head in heads {
+            if let Some(head_changes) = changes.get(head) {
+                for tuple in head_changes.inserted.tuples.iter() {
+                    frontier
+                        .entry(head.clone())
+                        .or_default()
+                        .push(tuple.clone());
+                }
+            }
+        }
+
+        let mut derived: Vec<(Predicate, Tuple)> = vec![];
+        let mut indexes = HashMap::new();
+        for rule in rules.iter() {
+            for (idx, goal) in rule.body.iter().enumerate() {
+                let tuples = match goal {
+                    Goal::Atom(predicate, _) if !heads.contains(predicate) => changes
+                        .get(predicate)
+                        .map(|changes| &changes.inserted.tuples),
+                    Goal::Not(predicate, _) => changes
+                        .get(predicate)
+                        .map(|changes| &changes.deleted.tuples),
+                    _ => None,
+                };
+                if let Some(tuples) = tuples.filter(|tuples| !tuples.is_empty()) {
+                    let pass = Pass::new(changes).seeded(View::New, idx, tuples);
+                    self.fire(rule, pass, &mut indexes, &mut derived)?;
+                }
+            }
+        }
+
+        loop {
+            let mut indexes = HashMap::new();
+            for rule in rules.iter() {
+                for (idx, goal) in rule.body.iter().enumerate() {
+                    if let Goal::Atom(predicate, _) = goal {
+                        if let Some(tuples) = frontier.get(predicate) {
+                            let pass = Pass::new(changes).seeded(View::New, idx, tuples);
+                            self.fire(rule, pass, &mut indexes, &mut derived)?;
+                        }
+                    }
+                }
+            }
+            frontier.clear();
+            for (predicate, tuple) in derived.drain(..) {
+                if self.set(touched, &predicate, tuple.clone(), true) {
+                    frontier.entry(predicate).or_default().push(tuple);
+                }
+            }
+            if frontier.is_empty() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn derivable(
+        &self,
+        rules: &[Rule],
+        predicate: &Predicate,
+        tuple: &Tuple,
+        changes: &ChangeSet,
+    ) -> Result<bool, NotDatalog> {
+        let mut found = false;
+        for rule in rules.iter().filter(|rule| rule.head.0 == *predicate) {
+            let mut bindings = HashMap::new();
+            if !match_tuple(&rule.head.1, tuple, &mut bindings, &mut vec![]) {
+                continue;
+            }
+            self.join(
+                &rule.body,
+                0,
+                Pass::new(changes),
+                &mut HashMap::new(),
+                &mut bindings,
+                &mut |_| found = true,
+            )?;
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn strata(&self) -> Result<Vec<Vec<usize>>, NotDatalog> {
+        let mut graph: HashMap<&Predicate, Vec<(&Predicate, bool)>> = HashMap::new();
+        for rule in self.rules.iter() {
+            graph
+                .entry(&rule.head.0)
+                .or_default()
+                .extend(rule.dependencies());
+        }
+
+        let mut components = Tarjan::default();
+        let mut predicates: Vec<&Predicate> = graph.keys().copied().collect();
+        predicates.sort();
+        for predicate in predicates {
+            if !components.index.contains_key(predicate) {
+                components.visit(predicate, &graph);
+            }
+        }
+
+        let mut strata = vec![];
+        for component in components.components {
+            let rules: Vec<usize> = (0..self.rules.len())
+                .filter(|idx| component.contains(&&self.rules[*idx].head.0))
+                .collect();
+            let offending: Vec<Term> = rules
+                .iter()
+                .map(|idx| &self.rules[*idx])
+                .filter(|rule| {
+                    rule.dependencies()
+                        .iter()
+                        .any(|(predicate, negative)| *negative && component.contains(predicate))
+                })
+                .map(|rule| rule.clause.clone())
+                .collect();
+            if !offending.is_empty() {
+                return Err(NotDatalog::Unstratifiable(offending));
+            }
+            if !rules.is_empty() {
+                strata.push(rules);
+            }
+        }
+        Ok(strata)
+    }
+
+    fn evaluate(&mut self, stratum: &[usize]) -> Result<(), NotDatalog> {
+        let changes = HashMap::new();
+        let mut derived: Vec<(Predicate, Tuple)> = vec![];
+        let mut indexes = HashMap::new();
+        for rule in stratum.iter().map(|idx| &self.rules[*idx]) {
+            if rule.has_aggregate() {
+                self.fire_aggregate(rule, &changes, &mut indexes, &mut derived)?;
+            }
+        }
+        for (predicate, tuple) in derived {
+            self.relations.entry(predicate).or_default().insert(tuple);
+        }
+
+        let mut delta: HashMap<Predicate, Vec<Tuple>> = self
+            .relations
+            .iter()
+            .map(|(predicate, relation)| (predicate.clone(), relation.tuples.clone()))
+            .collect();
+        let mut first = true;
+
+        while first || delta.values().any(|tuples| !tuples.is_empty()) {
+            let mut derived: Vec<(Predicate, Tuple)> = vec![];
+            let mut indexes = HashMap::new();
+
+            for rule in stratum.iter().map(|idx| &self.rules[*idx]) {
+                if rule.has_aggregate() {
+                    continue;
+                }
+                let atoms: Vec<usize> = rule
+                    .body
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, goal)| matches!(goal, Goal::Atom(..)))
+                    .map(|(idx, _)| idx)
+                    .collect();
+
+                if atoms.is_empty() {
+                    if first {
+                        self.fire(rule, Pass::new(&changes), &mut indexes, &mut derived)?;
+                    }
+                    continue;
+                }
+                for idx in atoms {
+                    let Goal::Atom(predicate, _) = &rule.body[idx] else {
+                        unreachable!()
+                    };
+                    match delta.get(predicate) {
+                        Some(tuples) if !tuples.is_empty() => {
+                            let pass = Pass::new(&changes).seeded(View::New, idx, tuples);
+                            self.fire(rule, pass, &mut indexes, &mut derived)?
+                        }
+                        _ => (),
+                    }
+                }
+            }
+
+            first = false;
+            delta.clear();
+            for (predicate, tuple) in derived {
+                if self
+                    .relations
+                    .entry(predicate.clone())
+                    .or_default()
+                    .insert(tuple.clone())
+                {
+                    delta.entry(predicate).or_default().push(tuple);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fire(
+        &self,
+        rule: &Rule,
+        pass: Pass,
+        indexes: &mut Indexes,
+        derived: &mut Vec<(Predicate, Tuple)>,
+    ) -> Result<(), NotDatalog> {
+        let (predicate, args) = &rule.head;
+        self.join(
+            &rule.body,
+            0,
+            pass,
+            indexes,
+            &mut HashMap::new(),
+            &mut |bindings| {
+                let tuple = args.iter().map(|arg| substitute(arg, bindings)).collect();
+                derived.push((predicate.clone(), tuple));
+            },
+        )
+    }
+
+    fn fire_aggregate(
+        &self,
+        rule: &Rule,
+        changes: &ChangeSet,
+        indexes: &mut Indexes,
+        derived: &mut Vec<(Predicate, Tuple)>,
+    ) -> Result<(), NotDatalog> {
+        let (predicate, args) = &rule.head;
+        let mut groups: Vec<(Tuple, Vec<Tuple>)> = vec![];
+        let mut group_idx: HashMap<Tuple, usize> = HashMap::new();
+
+        self.join(
+            &rule.body,
+            0,
+            Pass::new(changes),
+            indexes,
+            &mut HashMap::new(),
+            &mut |bindings| {
+                let mut key = vec![];
+                let mut values = vec![];
+                for arg in args.iter() {
+                    match aggregate(arg) {
+                        Some((_, var)) => values.push(bindings[var].clone()),
+                        None => key.push(substitute(arg, bindings)),
+                    }
+                }
+                let idx = *group_idx.entry(key.clone()).or_insert_with(|| {
+                    groups.push((key, vec![]));
+                    groups.len() - 1
+                });
+                groups[idx].1.push(values);
+            },
+        )?;
+
+        for (key, rows) in groups {
+            let mut key = key.into_iter();
+            let mut column = 0;
+            let tuple = args
+                .iter()
+                .map(|arg| match aggregate(arg) {
+                    Some((op, _)) => {
+                        let value = compute_aggregate(op, rows.iter().map(|row| &row[column]));
+                        column += 1;
+                        value
+                    }
+                    None => Ok(key.next().unwrap()),
+                })
+                .collect::<Result<_, _>>()?;
+            derived.push((predicate.clone(), tuple));
+        }
+        Ok(())
+    }
+
+    fn join(
+        &self,
+        goals: &[Goal],
+        position: usize,
+        pass: Pass,
+        indexes: &mut Indexes,
+        bindings: &mut HashMap<String, Term>,
+        emit: &mut dyn FnMut(&HashMap<String, Term>),
+    ) -> Result<(), NotDatalog> {
+        let Some(goal) = goals.get(position) else {
+            emit(bindings);
+            return Ok(());
+        };
+        let seed = pass
+            .seed
+            .filter(|(seed_position, _)| *seed_position == position)
+            .map(|(_, tuples)| tuples);
+
+        match goal {
+            Goal::Atom(predicate, args) => {
+                let candidates: Vec<Tuple> = match seed {
+                    Some(tuples) => tuples.to_vec(),
+                    None => self.lookup(predicate, args, bindings, pass, indexes),
+                };
+                for tuple in candidates {
+                    let mut bound = vec![];
+                    if match_tuple(args, &tuple, bindings, &mut bound) {
+                        self.join(goals, position + 1, pass, indexes, bindings, emit)?;
+                    }
+                    for name in bound {
+                        bindings.remove(&name);
+                    }
+                }
+            }
+            Goal::Not(predicate, args) => match seed {
+                Some(tuples) => {
+                    let local: Vec<Term> = args
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, arg)| match arg {
+                            Term::Variable(name)
+                                if !bindings.contains_key(name)
+                                    && !goals[position + 1..]
+                                        .iter()
+                                        .any(|goal| goal_vars(goal).contains(&name)) =>
+                            {
+                                Term::Variable(format!("#{}", idx))
+                            }
+                            _ => arg.clone(),
+                        })
+                        .collect();
+                    for tuple in tuples {
+                        let mut bound = vec![];
+                        let matched = match_tuple(&local, tuple, bindings, &mut bound);
+                        bound.retain(|name| {
+                            if name.starts_with('#') {
+                                bindings.remove(name);
+                                false
+                            } else {
+                                true
+                            }
+                        });
+                        if matched
+                            && !(pass.check_seed
+                                && self.exists(predicate, args, bindings, pass, indexes))
+                        {
+                            self.join(goals, position + 1, pass, indexes, bindings, emit)?;
+                        }
+                        for name in bound {
+                            bindings.remove(&name);
+                        }
+                    }
+                }
+                None => {
+                    if !self.exists(predicate, args, bindings, pass, indexes) {
+                        self.join(goals, position + 1, pass, indexes, bindings, emit)?;
+                    }
+                }
+            },
+            Goal::Eq(left, right) => {
+                match (substitute(left, bindings), substitute(right, bindings)) {
+                    (Term::Variable(name), value) | (value, Term::Variable(name)) => {
+                        bindings.insert(name.clone(), value);
+                        self.join(goals, position + 1, pass, indexes, bindings, emit)?;
+                        bindings.remove(&name);
+                    }
+                    (left, right) => {
+                        if left == right {
+                            self.join(goals, position + 1, pass, indexes, bindings, emit)?;
+                        }
+                    }
+                }
+            }
+            Goal::Compare(op, left, right) => {
+                if compare(
+                    op,
+                    &substitute(left, bindings),
+                    &substitute(right, bindings),
+                )? {
+                    self.join(goals, position + 1, pass, indexes, bindings, emit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn exists(
+        &self,
+        predicate: &Predicate,
+        args: &[Term],
+        bindings: &mut HashMap<String, Term>,
+        pass: Pass,
+        indexes: &mut Indexes,
+    ) -> bool {
+        self.lookup(predicate, args, bindings, pass, indexes)
+            .iter()
+            .any(|tuple| {
+                let mut bound = vec![];
+                let matched = match_tuple(args, tuple, bindings, &mut bound);
+                for name in bound {
+                    bindings.remove(&name);
+                }
+                matched
+            })
+    }
+
+    fn lookup(
+        &self,
+        predicate: &Predicate,
+        args: &[Term],
+        bindings: &HashMap<String, Term>,
+        pass: Pass,
+        indexes: &mut Indexes,
+    ) -> Vec<Tuple> {
+        let Some(relation) = self.relations.get(predicate) else {
+            return vec![];
+        };
+        let args: Vec<Term> = args.iter().map(|arg| substitute(arg, bindings)).collect();
+        let positions: Vec<usize> = (0..args.len())
+            .filter(|idx| !matches!(args[*idx], Term::Variable(_)))
+            .collect();
+        let key: Tuple = positions.iter().map(|pos| args[*pos].clone()).collect();
+
+        let mut tuples: Vec<Tuple> = if positions.is_empty() {
+            relation.tuples.clone()
+        } else {
+            let index = indexes
+                .entry((predicate.clone(), positions.clone()))
+                .or_insert_with(|| {
+                    let mut index: Index = HashMap::new();
+                    for (idx, tuple) in relation.tuples.iter().enumerate() {
+                        let key = positions.iter().map(|pos| tuple[*pos].clone()).collect();
+                        index.entry(key).or_default().push(idx);
+                    }
+                    index
+                });
+            match index.get(&key) {
+                Some(idxs) => idxs
+                    .iter()
+                    .map(|idx| relation.tuples[*idx].clone())
+                    .collect(),
+                None => vec![],
+            }
+        };
+
+        if pass.view == View::Old {
+            if let Some(changes) = pass.changes.get(predicate) {
+                tuples.retain(|tuple| !changes.inserted.contains(tuple));
+                tuples.extend(
+                    changes
+                        .deleted
+                        .tuples
+                        .iter()
+                        .filter(|tuple| {
+                            positions.iter().zip(&key).all(|(pos, v)| tuple[*pos] == *v)
+                        })
+                        .cloned(),
+                );
+            }
+        }
+        tuples
+    }
+}
+
+fn fact(clause: &Term) -> Result<(Predicate, Tuple), NotDatalog> {
+    let Term::CompoundTerm(name, args) = clause else {
+        return Err(NotDatalog::UnsupportedGoal(clause.clone()));
+    };
+    if let Some(arg) = args.iter().find(|arg| !is_constant(arg)) {
+        return match arg {
+            Term::Variable(var) => Err(NotDatalog::UnsafeVariable(clause.clone(), var.clone())),
+            _ => Err(NotDatalog::FunctionSymbol(clause.clone())),
+        };
+    }
+    Ok(((name.clone(), args.len()), args.clone()))
+}
+
+fn goal_vars(goal: &Goal) -> Vec<&String> {
+    let values: Vec<&Term> = match goal {
+        Goal::Atom(_, args) | Goal::Not(_, args) => args.iter().collect(),
+        Goal::Eq(left, right) | Goal::Compare(_, left, right) => vec![left, right],
+    };
+    values
+        .into_iter()
+        .filter_map(|value| match value {
+            Term::Variable(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Tarjan<'a> {
+    index: HashMap<&'a Predicate, usize>,
+    lowlink: HashMap<&'a Predicate, usize>,
+    stack: Vec<&'a Predicate>,
+    components: Vec<Vec<&'a Predicate>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(
+        &mut self,
+        predicate: &'a Predicate,
+        graph: &HashMap<&'a Predicate, Vec<(&'a Predicate, bool)>>,
+    ) {
+        let idx = self.index.len();
+        self.index.insert(predicate, idx);
+        self.lowlink.insert(predicate, idx);
+        self.stack.push(predicate);
+
+        for (dependency, _) in graph.get(predicate).into_iter().flatten() {
+            if !self.index.contains_key(dependency) {
+                self.visit(dependency, graph);
+                let low = self.lowlink[predicate].min(self.lowlink[dependency]);
+                self.lowlink.insert(predicate, low);
+            } else if self.stack.contains(dependency) {
+                let low = self.lowlink[predicate].min(self.index[dependency]);
+                self.lowlink.insert(predicate, low);
+            }
+        }
+
+        if self.lowlink[predicate] == idx {
+            let mut component = vec![];
+            while let Some(member) = self.stack.pop() {
+                component.push(member);
+                if member == predicate {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+fn aggregate(value: &Term) -> Option<(Aggregate, &String)> {
+    let Term::CompoundTerm(name, args) = value else {
+        return None;
+    };
+    let op = match name.as_str() {
+        "count" => Aggregate::Count,
+        "sum" => Aggregate::Sum,
+        "min" => Aggregate::Min,
+        "max" => Aggregate::Max,
+        _ => return None,
+    };
+    match args.as_slice() {
+        [Term::Variable(var)] => Some((op, var)),
+        _ => None,
+    }
+}
+
+fn compute_aggregate<'a>(
+    op: Aggregate,
+    values: impl Iterator<Item = &'a Term>,
+) -> Result<Term, NotDatalog> {
+    let ints = values
+        .map(|value| match value {
+            Term::Int(val) => Ok(*val),
+            _ if op == Aggregate::Count => Ok(1),
+            _ => Err(NotDatalog::Type("integer".to_string(), value.clone())),
+        })
+        .collect::<Result<Vec<usize>, _>>()?;
+    let value = match op {
+        Aggregate::Count => ints.len(),
+        Aggregate::Sum => ints.into_iter().sum(),
+        Aggregate::Min => ints.into_iter().min().unwrap_or_default(),
+        Aggregate::Max => ints.into_iter().max().unwrap_or_default(),
+    };
+    Ok(Term::Int(value))
+}
+
+fn is_variable(value: &Term) -> bool {
+    matches!(value, Term::Variable(_))
+}
+
+fn is_constant(value: &Term) -> bool {
+    match value {
+        Term::Int(_) | Term::Str(_) => true,
+        Term::CompoundTerm(_, args) => args.is_empty(),
+        _ => false,
+    }
+}
+
+fn body_goals(clause: &Term, body: &Term) -> Result<Vec<Goal>, NotDatalog> {
+    let check = |value: &Term| match value {
+        Term::Variable(_) => Ok(value.clone()),
+        _ if is_constant(value) => Ok(value.clone()),
+        _ => Err(NotDatalog::FunctionSymbol(clause.clone())),
+    };
+
+    match body {
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => {
+            let mut goals = body_goals(clause, &args[0])?;
+            goals.extend(body_goals(clause, &args[1])?);
+            Ok(goals)
+        }
+        Term::CompoundTerm(name, args) if (name == "not" || name == "\\+") && args.len() == 1 => {
+            match body_goals(clause, &args[0])?.as_slice() {
+                [Goal::Atom(predicate, args)] => {
+                    Ok(vec![Goal::Not(predicate.clone(), args.clone())])
+                }
+                _ => Err(NotDatalog::UnsupportedGoal(body.clone())),
+            }
+        }
+        Term::CompoundTerm(name, args) if name == "=" && args.len() == 2 => {
+            Ok(vec![Goal::Eq(check(&args[0])?, check(&args[1])?)])
+        }
+        Term::CompoundTerm(name, args) if args.len() == 2 && comparison(name).is_some() => {
+            Ok(vec![Goal::Compare(
+                comparison(name).unwrap(),
+                check(&args[0])?,
+                check(&args[1])?,
+            )])
+        }
+        Term::CompoundTerm(name, args) => Ok(vec![Goal::Atom(
+            (name.clone(), args.len()),
+            args.iter().map(check).collect::<Result<_, _>>()?,
+        )]),
+        _ => Err(NotDatalog::UnsupportedGoal(body.clone())),
+    }
+}
+
+fn comparison(name: &str) -> Option<Comparison> {
+    match name {
+        ">" => Some(Comparison::GreaterThan),
+        "<" => Some(Comparison::LessThan),
+        ">=" => Some(Comparison::GreaterThanEqual),
+        "<=" => Some(Comparison::LessThanEqual),
+        _ => None,
+    }
+}
+
+fn schedule(clause: &Term, head: &[Term], body: Vec<Goal>) -> Result<Vec<Goal>, NotDatalog> {
+    let mut bound: HashSet<String> = HashSet::new();
+    let mut pending = body;
+    let mut ordered = vec![];
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|goal| match goal {
+            Goal::Atom(_, _) => true,
+            Goal::Eq(left, right) => [left, right].into_iter().any(|side| match side {
+                Term::Variable(name) => bound.contains(name),
+                _ => true,
+            }),
+            Goal::Not(_, _) | Goal::Compare(_, _, _) => {
+                goal_vars(goal).iter().all(|name| bound.contains(*name))
+            }
+        });
+        let Some(idx) = ready else {
+            let name = pending
+                .iter()
+                .flat_map(goal_vars)
+                .find(|name| !bound.contains(*name))
+                .unwrap();
+            return Err(NotDatalog::UnsafeVariable(clause.clone(), name.clone()));
+        };
+        let goal = pending.remove(idx);
+        if let Goal::Atom(_, _) | Goal::Eq(_, _) = goal {
+            bound.extend(goal_vars(&goal).into_iter().cloned());
+        }
+        ordered.push(goal);
+    }
+
+    for arg in head {
+        let var = match (arg, aggregate(arg)) {
+            (_, Some((_, name))) => name,
+            (Term::Variable(name), None) => name,
+            _ => continue,
+        };
+        if !bound.contains(var) {
+            return Err(NotDatalog::UnsafeVariable(clause.clone(), var.clone()));
+        }
+    }
+    Ok(ordered)
+}
+
+fn match_tuple(
+    args: &[Term],
+    tuple: &[Term],
+    bindings: &mut HashMap<String, Term>,
+    bound: &mut Vec<String>,
+) -> bool {
+    for (arg, value) in args.iter().zip(tuple) {
+        match arg {
+            Term::Variable(name) => match bindings.get(name) {
+                Some(existing) if existing != value => return false,
+                Some(_) => (),
+                None => {
+                    bindings.insert(name.clone(), value.clone());
+                    bound.push(name.clone());
+                }
+            },
+            _ if arg != value => return false,
+            _ => (),
+        }
+    }
+    true
+}
+
+fn substitute(value: &Term, bindings: &HashMap<String, Term>) -> Term {
+    match value {
+        Term::Variable(name) => bindings.get(name).cloned().unwrap_or(value.clone()),
+        _ => value.clone(),
+    }
+}
+
+fn compare(op: &Comparison, left: &Term, right: &Term) -> Result<bool, NotDatalog> {
+    let (left, right) = match (left, right) {
+        (Term::Int(left), Term::Int(right)) => (left, right),
+        (Term::Int(_), culprit) | (culprit, _) => {
+            return Err(NotDatalog::Type("integer".to_string(), culprit.clone()))
+        }
+    };
+    Ok(match op {
+        Comparison::GreaterThan => left > right,
+        Comparison::LessThan => left < right,
+        Comparison::GreaterThanEqual => left >= right,
+        Comparison::LessThanEqual => left <= right,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser::Parser};
+
+    const RULES: &str = "
+        path(?x, ?y) :- edge(?x, ?y).
+        path(?x, ?z) :- path(?x, ?y), edge(?y, ?z).
+        node(?x) :- edge(?x, ?y).
+        node(?y) :- edge(?x, ?y).
+        unreachable(?x, ?y) :- node(?x), node(?y), not path(?x, ?y).
+        degree(?x, count(?y)) :- edge(?x, ?y).
+    ";
+
+    fn datalog(source: &str) -> Datalog {
+        let tokens = lexer::tokenize(source.to_string()).unwrap();
+        Datalog::new(Parser::new(tokens).parse().unwrap()).unwrap()
+    }
+
+    fn facts(datalog: &Datalog) -> HashSet<Term> {
+        datalog
+            .relations
+            .iter()
+            .flat_map(|((name, _), relation)| {
+                relation
+                    .tuples
+                    .iter()
+                    .map(|tuple| Term::CompoundTerm(name.clone(), tuple.clone()))
+            })
+            .collect()
+    }
+
+    fn edge(from: usize, to: usize) -> Term {
+        Term::CompoundTerm("edge".to_string(), vec![Term::Int(from), Term::Int(to)])
+    }
+
+    #[test]
+    fn updates_match_full_recompute() {
+        let mut incremental = datalog(RULES);
+        let mut edges: HashSet<(usize, usize)> = HashSet::new();
+        let mut seed: usize = 7;
+        let mut random = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+
+        for _ in 0..60 {
+            let mut inserted = vec![];
+            let mut retracted = vec![];
+            for _ in 0..1 + random(3) {
+                let (from, to) = (random(6), random(6));
+                if random(3) == 0 {
+                    edges.remove(&(from, to));
+                    inserted.retain(|fact| *fact != edge(from, to));
+                    retracted.push(edge(from, to));
+                } else {
+                    edges.insert((from, to));
+                    retracted.retain(|fact| *fact != edge(from, to));
+                    inserted.push(edge(from, to));
+                }
+            }
+
+            let before = facts(&incremental);
+            let delta = incremental.update(inserted, retracted).unwrap();
+            let after = facts(&incremental);
+
+            let source: String = edges
+                .iter()
+                .map(|(from, to)| format!("edge({}, {}).\n", from, to))
+                .collect();
+            assert_eq!(after, facts(&datalog(&(source + RULES))));
+
+            let derived =
+                |fact: &&Term| !matches!(fact, Term::CompoundTerm(name, _) if name == "edge");
+            let inserted: HashSet<Term> =
+                after.difference(&before).filter(derived).cloned().collect();
+            let deleted: HashSet<Term> =
+                before.difference(&after).filter(derived).cloned().collect();
+            assert_eq!(delta.inserted.into_iter().collect::<HashSet<_>>(), inserted);
+            assert_eq!(delta.deleted.into_iter().collect::<HashSet<_>>(), deleted);
+        }
+    }
+
+    fn parse(source: &str) -> Result<Datalog, NotDatalog> {
+        let tokens = lexer::tokenize(source.to_string()).unwrap();
+        Datalog::new(Parser::new(tokens).parse().unwrap())
+    }
+
+    fn query(datalog: &Datalog, source: &str) -> Result<Vec<String>, NotDatalog> {
+        let tokens = lexer::tokenize(source.to_string()).unwrap();
+        let query = Parser::new(tokens).parse_query().unwrap();
+        let mut answers: Vec<String> = datalog
+            .query(&query)?
+            .iter()
+            .map(|answer| {
+                let mut bindings: Vec<String> = answer
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                bindings.sort();
+                bindings.join(" ")
+            })
+            .collect();
+        answers.sort();
+        Ok(answers)
+    }
+
+    #[test]
+    fn comparisons_require_integers() {
+        let datalog = datalog("age(alice, 30). age(bob, unknown).");
+        assert_eq!(
+            query(&datalog, "age(alice, ?a), ?a >= 30.").unwrap(),
+            ["a=30"]
+        );
+        assert_eq!(
+            query(&datalog, "age(bob, ?a), ?a < 1."),
+            Err(NotDatalog::Type(
+                "integer".to_string(),
+                Term::CompoundTerm("unknown".to_string(), vec![])
+            ))
+        );
+        assert!(matches!(
+            parse("
+                age(alice, 30). age(bob, unknown).
+                older(?x, ?y) :- age(?x, ?a), age(?y, ?b), ?a > ?b.
+            "),
+            Err(NotDatalog::Type(expected, _)) if expected == "integer"
+        ));
+    }
+
+    #[test]
+    fn unsafe_rules_are_rejected() {
+        for (source, var) in [
+            ("p(?x, ?y) :- q(?x).", "y"),
+            ("p(?x) :- q(?y), not r(?x).", "x"),
+            ("p(?x) :- q(?x), \\+ r(?x, ?z).", "z"),
+            ("p(?x) :- q(?x), ?y > 1.", "y"),
+            ("p(?x) :- ?x = ?y.", "x"),
+            ("p(count(?y)) :- q(?x).", "y"),
+        ] {
+            assert!(
+                matches!(parse(source), Err(NotDatalog::UnsafeVariable(_, name)) if name == var),
+                "{source}"
+            );
+        }
+        assert!(matches!(
+            parse("p(f(?x)) :- q(?x)."),
+            Err(NotDatalog::FunctionSymbol(_))
+        ));
+        assert!(parse("p(?x) :- ?y > 1, q(?x, ?y), ?z = ?x, not r(?z).").is_ok());
+    }
+
+    #[test]
+    fn unstratifiable_programs_are_rejected() {
+        let Err(NotDatalog::Unstratifiable(clauses)) = parse(
+            "
+            move(a, b). move(b, a). move(b, c).
+            win(?x) :- move(?x, ?y), not win(?y).
+        ",
+        ) else {
+            panic!("expected an unstratifiable program");
+        };
+        assert_eq!(clauses.len(), 1);
+        assert!(matches!(
+            parse("
+                p(?x) :- q(?x), not r(?x).
+                r(?x) :- q(?x), not p(?x).
+            "),
+            Err(NotDatalog::Unstratifiable(clauses)) if clauses.len() == 2
+        ));
+        assert!(matches!(
+            parse("total(sum(?n)) :- total(?n)."),
+            Err(NotDatalog::Unstratifiable(_))
+        ));
+    }
+
+    #[test]
+    fn negation() {
+        let datalog = datalog(
+            "
+            node(a). node(b). node(c).
+            edge(a, b).
+            target(?x) :- edge(?y, ?x).
+            origin(?x) :- edge(?x, ?y).
+            source(?x) :- node(?x), \\+ target(?x).
+            sink(?x) :- node(?x), not origin(?x).
+        ",
+        );
+        assert_eq!(query(&datalog, "source(?x).").unwrap(), ["x=a", "x=c"]);
+        assert_eq!(query(&datalog, "sink(?x).").unwrap(), ["x=b", "x=c"]);
+    }
+
+    #[test]
+    fn aggregates() {
+        let mut datalog = datalog(
+            "
+            score(alice, 3). score(alice, 5). score(bob, 4).
+            count_of(?x, count(?s)) :- score(?x, ?s).
+            sum_of(?x, sum(?s)) :- score(?x, ?s).
+            min_of(?x, min(?s)) :- score(?x, ?s).
+            max_of(?x, max(?s)) :- score(?x, ?s).
+            players(count(?x)) :- score(?x, ?s).
+        ",
+        );
+        assert_eq!(
+            query(&datalog, "count_of(?x, ?n).").unwrap(),
+            ["n=1 x=bob", "n=2 x=alice"]
+        );
+        assert_eq!(
+            query(&datalog, "sum_of(?x, ?n).").unwrap(),
+            ["n=4 x=bob", "n=8 x=alice"]
+        );
+        assert_eq!(
+            query(&datalog, "min_of(?x, ?n).").unwrap(),
+            ["n=3 x=alice", "n=4 x=bob"]
+        );
+        assert_eq!(
+            query(&datalog, "max_of(?x, ?n).").unwrap(),
+            ["n=4 x=bob", "n=5 x=alice"]
+        );
+        assert_eq!(query(&datalog, "players(?n).").unwrap(), ["n=3"]);
+
+        let score = |name: &str, value: Term| {
+            Term::CompoundTerm(
+                "score".to_string(),
+                vec![Term::CompoundTerm(name.to_string(), vec![]), value],
+            )
+        };
+        datalog.insert(vec![score("bob", Term::Int(9))]).unwrap();
+        assert_eq!(query(&datalog, "max_of(bob, ?n).").unwrap(), ["n=9"]);
+        datalog.retract(vec![score("alice", Term::Int(3))]).unwrap();
+        assert_eq!(query(&datalog, "min_of(alice, ?n).").unwrap(), ["n=5"]);
+
+        let unknown = Term::CompoundTerm("unknown".to_string(), vec![]);
+        assert_eq!(
+            datalog.insert(vec![score("carol", unknown.clone())]),
+            Err(NotDatalog::Type("integer".to_string(), unknown.clone()))
+        );
+        assert!(matches!(
+            parse("
+                score(alice, 3). score(bob, unknown).
+                total(count(?s)) :- score(?x, ?s).
+                best(max(?s)) :- score(?x, ?s).
+            "),
+            Err(NotDatalog::Type(expected, culprit)) if expected == "integer" && culprit == unknown
+        ));
+        assert!(parse(
+            "
+            score(alice, 3). score(bob, unknown).
+            total(count(?s)) :- score(?x, ?s).
+        "
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+use std::{fmt, io};
+
+use crate::parser::Term;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Instantiation,
+    Type(String, Term),
+    Domain(String, Term),
+    Existence(String, usize),
+    Representation(String),
+    Syntax(String),
+    Io(String),
+    Uncaught(Term),
+}
+
+impl Error {
+    pub fn from_ball(ball: Term) -> Error {
+        let formal = match &ball {
+            Term::CompoundTerm(name, args) if name == "error" && args.len() == 2 => &args[0],
+            _ => return Error::Uncaught(ball),
+        };
+        match formal {
+            Term::CompoundTerm(name, args) if name == "instantiation_error" && args.is_empty() => {
+                Error::Instantiation
+            }
+            Term::CompoundTerm(name, args) if name == "type_error" && args.len() == 2 => {
+                match &args[0] {
+                    Term::CompoundTerm(expected, none) if none.is_empty() => {
+                        Error::Type(expected.clone(), args[1].clone())
+                    }
+                    _ => Error::Uncaught(ball),
+                }
+            }
+            Term::CompoundTerm(name, args) if name == "domain_error" && args.len() == 2 => {
+                match &args[0] {
+                    Term::CompoundTerm(domain, none) if none.is_empty() => {
+                        Error::Domain(domain.clone(), args[1].clone())
+                    }
+                    _ => Error::Uncaught(ball),
+                }
+            }
+            Term::CompoundTerm(name, args) if name == "existence_error" && args.len() == 2 => {
+                match &args[1] {
+                    Term::CompoundTerm(slash, indicator) if slash == "/" => {
+                        match (&indicator[0], &indicator[1]) {
+                            (Term::CompoundTerm(name, none), Term::Int(arity))
+                                if none.is_empty() =>
+                            {
+                                Error::Existence(name.clone(), *arity)
+                            }
+                            _ => Error::Uncaught(ball),
+                        }
+                    }
+                    _ => Error::Uncaught(ball),
+                }
+            }
+            Term::CompoundTerm(name, args) if name == "representation_error" && args.len() == 1 => {
+                match &args[0] {
+                    Term::CompoundTerm(flag, none) if none.is_empty() => {
+                        Error::Representation(flag.clone())
+                    }
+                    _ => Error::Uncaught(ball),
+                }
+            }
+            Term::CompoundTerm(name, args) if name == "syntax_error" && args.len() == 1 => {
+                match &args[0] {
+                    Term::CompoundTerm(description, none) if none.is_empty() => {
+                        Error::Syntax(description.clone())
+                    }
+                    _ => Error::Uncaught(ball),
+                }
+            }
+            Term::CompoundTerm(name, args) if name == "io_error" && args.len() == 1 => {
+                match &args[0] {
+                    Term::CompoundTerm(description, none) if none.is_empty() => {
+                        Error::Io(description.clone())
+                    }
+                    _ => Error::Uncaught(ball),
+                }
+            }
+            _ => Error::Uncaught(ball),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Instantiation => write!(f, "arguments are not sufficiently instantiated"),
+            Error::Type(expected, culprit) => {
+                write!(f, "type error: expected {}, found {}", expected, culprit)
+            }
+            Error::Domain(domain, culprit) => {
+                write!(f, "domain error: expected {}, found {}", domain, culprit)
+            }
+            Error::Existence(name, arity) => write!(f, "unknown procedure {}/{}", name, arity),
+            Error::Representation(flag) => write!(f, "representation error: {}", flag),
+            Error::Syntax(description) => write!(f, "syntax error: {}", description),
+            Error::Io(description) => write!(f, "io error: {}", description),
+            Error::Uncaught(ball) => write!(f, "uncaught exception {}", ball),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn atom(name: &str) -> Term {
+    Term::CompoundTerm(name.to_string(), vec![])
+}
+
+pub fn instantiation_error() -> Term {
+    atom("instantiation_error")
+}
+
+pub fn type_error(expected: &str, culprit: Term) -> Term {
+    Term::CompoundTerm("type_error".to_string(), vec![atom(expected), culprit])
+}
+
+pub fn domain_error(domain: &str, culprit: Term) -> Term {
+    Term::CompoundTerm("domain_error".to_string(), vec![atom(domain), culprit])
+}
+
+pub fn existence_error(name: &str, arity: usize) -> Term {
+    let indicator = Term::CompoundTerm("/".to_string(), vec![atom(name), Term::Int(arity)]);
+    Term::CompoundTerm(
+        "existence_error".to_string(),
+        vec![atom("procedure"), indicator],
+    )
+}
+
+pub fn representation_error(flag: &str) -> Term {
+    Term::CompoundTerm("representation_error".to_string(), vec![atom(flag)])
+}
+
+pub fn syntax_error(description: &str) -> Term {
+    Term::CompoundTerm("syntax_error".to_string(), vec![atom(description)])
+}
+
+pub fn io_error(description: &str) -> Term {
+    Term::CompoundTerm("io_error".to_string(), vec![atom(description)])
+}
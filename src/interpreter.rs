@@ -1,389 +1,3490 @@
-use std::collections::HashMap;
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+    rc::Rc,
+};
 
-use crate::parser::Value;
+use crate::{
+    error::{self, Error},
+    journal::{Journal, Op},
+    library,
+    parser::{Clause, Item, Term},
+    snapshot,
+};
+
+type Clauses = Rc<Predicate>;
+
+#[derive(Debug, Default, Clone)]
+struct Index {
+    keys: HashMap<Term, Vec<usize>>,
+    variable: Vec<usize>,
+}
+
+impl Index {
+    fn add(&mut self, position: usize, clause: &Clause) {
+        let Term::CompoundTerm(_, args) = &clause.head else {
+            return;
+        };
+        match args.first() {
+            Some(Term::Variable(_)) => {
+                for positions in self.keys.values_mut() {
+                    positions.push(position);
+                }
+                self.variable.push(position);
+            }
+            Some(arg) => {
+                if let Some(key) = index_key(arg) {
+                    self.keys
+                        .entry(key)
+                        .or_insert_with(|| self.variable.clone())
+                        .push(position);
+                }
+            }
+            None => (),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Predicate {
+    clauses: Vec<Rc<Clause>>,
+    index: OnceCell<Index>,
+}
+
+impl Predicate {
+    fn index(&self) -> &Index {
+        self.index.get_or_init(|| {
+            let mut index = Index::default();
+            for (position, clause) in self.clauses.iter().enumerate() {
+                index.add(position, clause);
+            }
+            index
+        })
+    }
+
+    fn candidates(&self, key: Option<&Term>) -> Option<&[usize]> {
+        let index = self.index();
+        Some(index.keys.get(key?).unwrap_or(&index.variable))
+    }
+
+    fn push(&mut self, clause: Rc<Clause>) {
+        if let Some(index) = self.index.get_mut() {
+            index.add(self.clauses.len(), &clause);
+        }
+        self.clauses.push(clause);
+    }
+
+    fn insert_first(&mut self, clause: Rc<Clause>) {
+        self.index = OnceCell::new();
+        self.clauses.insert(0, clause);
+    }
+
+    fn remove(&mut self, position: usize) {
+        self.index = OnceCell::new();
+        self.clauses.remove(position);
+    }
+}
+
+fn index_key(value: &Term) -> Option<Term> {
+    match value {
+        Term::Int(_) | Term::Str(_) => Some(value.clone()),
+        Term::CompoundTerm(_, args) if args.is_empty() => Some(value.clone()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Store {
+    predicates: HashMap<(String, usize), Clauses>,
+    log: Option<Vec<Op>>,
+    undo: HashMap<(String, usize), Option<Clauses>>,
+}
+
+impl Store {
+    fn touch(&mut self, key: &(String, usize)) {
+        if self.log.is_some() && !self.undo.contains_key(key) {
+            self.undo
+                .insert(key.clone(), self.predicates.get(key).cloned());
+        }
+    }
+
+    fn rollback(&mut self, undo: HashMap<(String, usize), Option<Clauses>>) {
+        for (key, clauses) in undo {
+            match clauses {
+                Some(clauses) => self.predicates.insert(key, clauses),
+                None => self.predicates.remove(&key),
+            };
+        }
+    }
+
+    fn asserta(&mut self, clause: Clause) -> Result<(), Error> {
+        let key = clause.key()?;
+        self.touch(&key);
+        if let Some(log) = &mut self.log {
+            log.push(Op::Asserta(clause.clone()));
+        }
+        let clauses = self.predicates.entry(key).or_default();
+        Rc::make_mut(clauses).insert_first(Rc::new(clause));
+        Ok(())
+    }
+
+    fn assertz(&mut self, clause: Clause) -> Result<(), Error> {
+        let key = clause.key()?;
+        self.touch(&key);
+        if let Some(log) = &mut self.log {
+            log.push(Op::Assertz(clause.clone()));
+        }
+        let clauses = self.predicates.entry(key).or_default();
+        Rc::make_mut(clauses).push(Rc::new(clause));
+        Ok(())
+    }
+
+    fn clauses(&self, key: &(String, usize)) -> Clauses {
+        self.predicates.get(key).cloned().unwrap_or_default()
+    }
+
+    fn remove(&mut self, clause: &Rc<Clause>) -> bool {
+        let Ok(key) = clause.key() else {
+            return false;
+        };
+        let Some(idx) = self
+            .predicates
+            .get(&key)
+            .and_then(|clauses| clauses.clauses.iter().position(|c| Rc::ptr_eq(c, clause)))
+        else {
+            return false;
+        };
+        self.touch(&key);
+        if let Some(log) = &mut self.log {
+            log.push(Op::Retract(clause.as_ref().clone()));
+        }
+        Rc::make_mut(self.predicates.get_mut(&key).unwrap()).remove(idx);
+        true
+    }
+
+    fn declare(&mut self, key: (String, usize)) {
+        self.touch(&key);
+        self.predicates.entry(key).or_default();
+    }
+
+    fn apply(&mut self, op: Op) -> Result<(), Error> {
+        match op {
+            Op::Asserta(clause) => self.asserta(clause),
+            Op::Assertz(clause) => self.assertz(clause),
+            Op::Retract(clause) => {
+                let clauses = self.clauses(&clause.key()?);
+                if let Some(clause) = clauses.clauses.iter().find(|c| ***c == clause) {
+                    self.remove(clause);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unknown {
+    Error,
+    Warning,
+    Fail,
+}
+
+impl Unknown {
+    fn name(&self) -> &'static str {
+        match self {
+            Unknown::Error => "error",
+            Unknown::Warning => "warning",
+            Unknown::Fail => "fail",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Unknown> {
+        match name {
+            "error" => Some(Unknown::Error),
+            "warning" => Some(Unknown::Warning),
+            "fail" => Some(Unknown::Fail),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct DB {
-    facts: Vec<Value>,
+    store: RefCell<Store>,
+    tabled: HashSet<(String, usize)>,
+    persistent: HashSet<(String, usize)>,
+    unknown: Cell<Unknown>,
+    journal: RefCell<Option<Journal>>,
 }
 
 impl DB {
-    pub fn new(facts: Vec<Value>) -> DB {
-        DB { facts }
+    pub fn new(program: Vec<Item>) -> DB {
+        DB::consult(program, |_, _| ())
     }
 
-    pub fn query(
-        &self,
-        query_node: &Value,
-        env: &mut HashMap<String, Value>,
-    ) -> Option<HashMap<String, Value>> {
-        assert!(matches!(query_node, Value::CompoundTerm(_, _)));
+    pub fn consult(program: Vec<Item>, mut on_query: impl FnMut(&DB, &Term)) -> DB {
+        let mut db = DB {
+            store: RefCell::default(),
+            tabled: HashSet::new(),
+            persistent: HashSet::new(),
+            unknown: Cell::new(Unknown::Error),
+            journal: RefCell::new(None),
+        };
+
+        let defined: HashSet<_> = program
+            .iter()
+            .filter_map(|item| match item {
+                Item::Clause(clause) => clause.key().ok(),
+                Item::Directive(_) | Item::Query(_) => None,
+            })
+            .collect();
+        for clause in library::clauses() {
+            if !clause.key().is_ok_and(|key| defined.contains(&key)) {
+                db.consult_clause(clause.clone());
+            }
+        }
 
-        for fact in self.facts.iter() {
-            let mut map: HashMap<String, Value> = env.clone();
-            if self.unify(query_node, fact, &mut map) {
-                let mut vars: HashMap<String, Value> = HashMap::new();
-                DB::get_vars(query_node, &map, &mut vars);
-                for (key, val) in vars.clone().iter() {
-                    let actual_val = DB::instantiate(val, &map);
-                    vars.insert(key.clone(), actual_val.clone());
-                    env.insert(key.clone(), actual_val.clone());
+        let mut initialization = vec![];
+        for item in program {
+            match item {
+                Item::Clause(clause) => db.consult_clause(clause),
+                Item::Directive(Term::CompoundTerm(name, mut args))
+                    if name == "initialization" && args.len() == 1 =>
+                {
+                    initialization.extend(args.pop());
                 }
+                Item::Directive(goal) => db.directive(&goal),
+                Item::Query(goal) => on_query(&db, &goal),
+            }
+        }
+        for goal in initialization {
+            db.run_directive(&goal);
+        }
+
+        db
+    }
+
+    fn consult_clause(&mut self, clause: Clause) {
+        let head = clause.head.clone();
+        if let Err(err) = self.store.get_mut().assertz(clause) {
+            eprintln!("Warning: clause for {} raised {}", head, err);
+        }
+    }
 
-                return Some(vars);
+    fn directive(&mut self, goal: &Term) {
+        let result = match goal {
+            Term::CompoundTerm(name, args) if name == "table" && args.len() == 1 => {
+                predicate_indicators(&args[0]).map(|keys| self.tabled.extend(keys))
+            }
+            Term::CompoundTerm(name, args) if name == "persistent" && args.len() == 1 => {
+                predicate_indicators(&args[0]).map(|keys| self.persistent.extend(keys))
+            }
+            Term::CompoundTerm(name, args) if name == "dynamic" && args.len() == 1 => {
+                predicate_indicators(&args[0]).map(|keys| {
+                    for key in keys {
+                        self.store.get_mut().declare(key);
+                    }
+                })
+            }
+            Term::CompoundTerm(name, args) if name == "set_prolog_flag" && args.len() == 2 => {
+                self.set_flag(&args[0], &args[1])
             }
+            _ => return self.run_directive(goal),
+        };
+        if let Err(formal) = result {
+            let ball =
+                Term::CompoundTerm("error".to_string(), vec![formal, error::atom("directive")]);
+            eprintln!(
+                "Warning: directive {} raised {}",
+                goal,
+                Error::from_ball(ball)
+            );
+        }
+    }
+
+    fn run_directive(&self, goal: &Term) {
+        match self.query(goal, &mut HashMap::new()) {
+            Ok(Some(_)) => (),
+            Ok(None) => eprintln!("Warning: directive failed: {}", goal),
+            Err(err) => eprintln!("Warning: directive {} raised {}", goal, err),
         }
+    }
 
-        None
+    pub fn set_unknown(&self, unknown: Unknown) {
+        self.unknown.set(unknown);
     }
 
-    fn get_vars(query: &Value, env: &HashMap<String, Value>, out: &mut HashMap<String, Value>) {
-        match query {
-            Value::CompoundTerm(_, values) => {
-                values.iter().for_each(|val| DB::get_vars(val, env, out))
+    fn set_flag(&self, flag: &Term, value: &Term) -> Result<(), Term> {
+        match (flag, value) {
+            (Term::Variable(_), _) | (_, Term::Variable(_)) => Err(error::instantiation_error()),
+            (Term::CompoundTerm(name, none), _) if name == "unknown" && none.is_empty() => {
+                let unknown = match value {
+                    Term::CompoundTerm(value, none) if none.is_empty() => Unknown::from_name(value),
+                    _ => None,
+                };
+                match unknown {
+                    Some(unknown) => {
+                        self.unknown.set(unknown);
+                        Ok(())
+                    }
+                    None => Err(error::domain_error(
+                        "flag_value",
+                        Term::CompoundTerm("+".to_string(), vec![flag.clone(), value.clone()]),
+                    )),
+                }
             }
-            Value::Predicate(_, _, _) => todo!(),
-            Value::List(values) => values.iter().for_each(|val| DB::get_vars(val, env, out)),
-            Value::Str(_) => (),
-            Value::Int(_) => (),
-            Value::Variable(name) => {
-                out.insert(name.clone(), env.get(name).unwrap().clone());
+            (Term::CompoundTerm(_, none), _) if none.is_empty() => {
+                Err(error::domain_error("prolog_flag", flag.clone()))
             }
-            Value::Eq(left, right) => match left.as_ref() {
-                Value::Variable(name) => {
-                    // println!("{:?} => {:?}", name, right);
-                    out.insert(name.clone(), right.as_ref().to_owned());
+            _ => Err(error::type_error("atom", flag.clone())),
+        }
+    }
+
+    pub fn open(program: Vec<Item>, dir: impl AsRef<Path>) -> io::Result<DB> {
+        let db = DB::new(program);
+        let (journal, recovered) = Journal::open(dir.as_ref())?;
+        let snapshotted = recovered.snapshot.is_some();
+        {
+            let mut store = db.store.borrow_mut();
+            if let Some(clauses) = recovered.snapshot {
+                store
+                    .predicates
+                    .retain(|key, _| !db.persistent.contains(key));
+                for clause in clauses {
+                    store.assertz(clause).map_err(invalid_data)?;
                 }
-                _ => panic!(),
-            },
-            Value::And(left, right) => {
-                DB::get_vars(left, env, out);
-                DB::get_vars(right, env, out);
             }
-            Value::LessThan(left, right) => {
-                DB::get_vars(left, env, out);
-                DB::get_vars(right, env, out);
+            for op in recovered.ops {
+                store.apply(op).map_err(invalid_data)?;
+            }
+            store.log = Some(vec![]);
+        }
+        *db.journal.borrow_mut() = Some(journal);
+
+        if !snapshotted {
+            db.compact()?;
+        }
+        Ok(db)
+    }
+
+    pub fn compact(&self) -> io::Result<()> {
+        match self.journal.borrow_mut().as_mut() {
+            Some(journal) => journal.compact(&self.persistent_clauses()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = snapshot::Writer::new();
+        writer.atom(self.unknown.get().name());
+        for indicators in [&self.tabled, &self.persistent] {
+            let mut indicators: Vec<&(String, usize)> = indicators.iter().collect();
+            indicators.sort();
+            writer.u32(indicators.len() as u32);
+            for (name, arity) in indicators {
+                writer.atom(name);
+                writer.u32(*arity as u32);
+            }
+        }
+
+        let store = self.store.borrow();
+        let mut keys: Vec<&(String, usize)> = store.predicates.keys().collect();
+        keys.sort();
+        writer.u32(keys.len() as u32);
+        for key in keys {
+            let predicate = &store.predicates[key];
+            writer.atom(&key.0);
+            writer.u32(key.1 as u32);
+            writer.u32(predicate.clauses.len() as u32);
+            for clause in predicate.clauses.iter() {
+                writer.value(&clause.to_term());
             }
-            Value::LessThanEqual(left, right) => {
-                DB::get_vars(left, env, out);
-                DB::get_vars(right, env, out);
+
+            let index = predicate.index();
+            writer.u32(index.variable.len() as u32);
+            for position in index.variable.iter() {
+                writer.u32(*position as u32);
             }
-            Value::GreaterThan(left, right) => {
-                DB::get_vars(left, env, out);
-                DB::get_vars(right, env, out);
+            let mut keys: Vec<_> = index.keys.iter().collect();
+            keys.sort_by(|(left, _), (right, _)| compare_terms(left, right));
+            writer.u32(keys.len() as u32);
+            for (key, positions) in keys {
+                writer.value(key);
+                writer.u32(positions.len() as u32);
+                for position in positions {
+                    writer.u32(*position as u32);
+                }
             }
-            Value::GreaterThanEqual(left, right) => {
-                DB::get_vars(left, env, out);
-                DB::get_vars(right, env, out);
+        }
+        fs::write(path, writer.finish())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<DB> {
+        let bytes = fs::read(path)?;
+        let mut reader = snapshot::Reader::new(&bytes)?;
+        let unknown = Unknown::from_name(&reader.atom()?).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "bad unknown flag in snapshot")
+        })?;
+        let mut indicators = || -> io::Result<HashSet<(String, usize)>> {
+            let count = reader.u32()?;
+            (0..count)
+                .map(|_| Ok((reader.atom()?, reader.u32()? as usize)))
+                .collect()
+        };
+        let tabled = indicators()?;
+        let persistent = indicators()?;
+
+        let mut store = Store::default();
+        for _ in 0..reader.u32()? {
+            let key = (reader.atom()?, reader.u32()? as usize);
+            let clauses: Vec<_> = (0..reader.u32()?)
+                .map(|_| Ok(Rc::new(Clause::from_term(reader.value()?))))
+                .collect::<io::Result<_>>()?;
+
+            let positions = |reader: &mut snapshot::Reader| -> io::Result<Vec<usize>> {
+                let positions = (0..reader.u32()?)
+                    .map(|_| Ok(reader.u32()? as usize))
+                    .collect::<io::Result<Vec<_>>>()?;
+                let ordered = positions.windows(2).all(|pair| pair[0] < pair[1]);
+                match positions.last() {
+                    Some(last) if !ordered || *last >= clauses.len() => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "bad index position in snapshot",
+                    )),
+                    _ => Ok(positions),
+                }
+            };
+            let mut index = Index {
+                variable: positions(&mut reader)?,
+                ..Index::default()
+            };
+            for _ in 0..reader.u32()? {
+                let key = reader.value()?;
+                index.keys.insert(key, positions(&mut reader)?);
             }
+
+            let predicate = Predicate {
+                clauses,
+                index: OnceCell::from(index),
+            };
+            store.predicates.insert(key, Rc::new(predicate));
         }
+
+        Ok(DB {
+            store: RefCell::new(store),
+            tabled,
+            persistent,
+            unknown: Cell::new(unknown),
+            journal: RefCell::new(None),
+        })
     }
 
-    fn instantiate(value: &Value, map: &HashMap<String, Value>) -> Value {
-        match value {
-            Value::CompoundTerm(_, _) => todo!(),
-            Value::Predicate(_, _, _) => todo!(),
-            Value::List(values) => Value::List(
-                values
+    fn persistent_clauses(&self) -> Vec<Clause> {
+        let store = self.store.borrow();
+        let mut keys: Vec<&(String, usize)> = self.persistent.iter().collect();
+        keys.sort();
+        keys.into_iter()
+            .flat_map(|key| {
+                store
+                    .clauses(key)
+                    .clauses
                     .iter()
-                    .map(|val| DB::instantiate(val, map))
-                    .collect::<Vec<_>>(),
-            ),
-            Value::Str(_) => value.to_owned(),
-            Value::Int(_) => value.to_owned(),
-            Value::Variable(name) => map.get(name).unwrap().to_owned(),
-            Value::Eq(left, right) => Value::Eq(
-                Box::new(DB::instantiate(left, map)),
-                Box::new(DB::instantiate(right, map)),
-            ),
-            Value::And(left, right) => Value::And(
-                Box::new(DB::instantiate(left, map)),
-                Box::new(DB::instantiate(right, map)),
-            ),
-            Value::LessThan(left, right) => Value::LessThan(
-                Box::new(DB::instantiate(left, map)),
-                Box::new(DB::instantiate(right, map)),
-            ),
-            Value::LessThanEqual(left, right) => Value::LessThanEqual(
-                Box::new(DB::instantiate(left, map)),
-                Box::new(DB::instantiate(right, map)),
-            ),
-            Value::GreaterThan(left, right) => Value::GreaterThan(
-                Box::new(DB::instantiate(left, map)),
-                Box::new(DB::instantiate(right, map)),
-            ),
-            Value::GreaterThanEqual(left, right) => Value::GreaterThanEqual(
-                Box::new(DB::instantiate(left, map)),
-                Box::new(DB::instantiate(right, map)),
-            ),
+                    .map(|c| c.as_ref().clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let undo = std::mem::take(&mut self.store.borrow_mut().undo);
+        let flushed = self.write_log();
+        if flushed.is_err() {
+            self.store.borrow_mut().rollback(undo);
         }
+        flushed
     }
 
-    fn unify(&self, query: &Value, fact: &Value, map: &mut HashMap<String, Value>) -> bool {
-        match (query, fact) {
-            (Value::CompoundTerm(name_a, args_a), Value::CompoundTerm(name_b, args_b)) => {
-                if name_a != name_b || args_a.len() != args_b.len() {
-                    return false;
+    fn write_log(&self) -> io::Result<()> {
+        let mut journal = self.journal.borrow_mut();
+        let Some(journal) = journal.as_mut() else {
+            return Ok(());
+        };
+        let ops: Vec<Op> = match self.store.borrow_mut().log.as_mut() {
+            Some(log) => std::mem::take(log),
+            None => return Ok(()),
+        };
+        let ops: Vec<Op> = ops
+            .into_iter()
+            .filter(|op| {
+                op.clause()
+                    .key()
+                    .is_ok_and(|key| self.persistent.contains(&key))
+            })
+            .collect();
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        journal.append(&ops)?;
+        if journal.needs_compaction() {
+            // the ops are already durable in the journal, so a failed compaction
+            // is retried on the next flush instead of being reported
+            journal.compact(&self.persistent_clauses()).ok();
+        }
+        Ok(())
+    }
+
+    fn persist<T>(&self, f: impl FnOnce() -> T) -> io::Result<T> {
+        let result = f();
+        self.flush()?;
+        Ok(result)
+    }
+
+    pub fn asserta(&self, clause: Clause) -> Result<(), Error> {
+        self.persist(|| self.store.borrow_mut().asserta(clause))?
+    }
+
+    pub fn assertz(&self, clause: Clause) -> Result<(), Error> {
+        self.persist(|| self.store.borrow_mut().assertz(clause))?
+    }
+
+    pub fn retract(&self, clause: &Term) -> Result<bool, Error> {
+        self.persist(|| retract(self, &self.store, clause))?
+    }
+
+    pub fn retract_all(&self, head: &Term) -> Result<(), Error> {
+        self.persist(|| retract_all(self, &self.store, head))?
+    }
+
+    pub fn query(
+        &self,
+        query_node: &Term,
+        env: &mut HashMap<String, Term>,
+    ) -> Result<Option<HashMap<String, Term>>, Error> {
+        self.persist(|| query(self, &self.store, query_node, env))?
+    }
+
+    pub fn query_all(&self, query_node: &Term) -> Result<Vec<HashMap<String, Term>>, Error> {
+        self.persist(|| query_all(self, &self.store, query_node))?
+    }
+
+    pub fn transaction<T, E>(&self, f: impl FnOnce(&Transaction) -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<io::Error>,
+    {
+        let mut store = self.store.borrow().clone();
+        store.log = Some(vec![]);
+        let tx = Transaction {
+            db: self,
+            store: RefCell::new(store),
+        };
+        let result = f(&tx)?;
+        let changes = tx.store.into_inner();
+        self.persist(|| {
+            let mut store = self.store.borrow_mut();
+            for key in changes.predicates.into_keys() {
+                store.declare(key);
+            }
+            for op in changes.log.into_iter().flatten() {
+                // every op already applied cleanly to the transaction's copy
+                store.apply(op).ok();
+            }
+        })?;
+        Ok(result)
+    }
+}
+
+pub struct Transaction<'a> {
+    db: &'a DB,
+    store: RefCell<Store>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn asserta(&self, clause: Clause) -> Result<(), Error> {
+        self.store.borrow_mut().asserta(clause)
+    }
+
+    pub fn assertz(&self, clause: Clause) -> Result<(), Error> {
+        self.store.borrow_mut().assertz(clause)
+    }
+
+    pub fn retract(&self, clause: &Term) -> Result<bool, Error> {
+        retract(self.db, &self.store, clause)
+    }
+
+    pub fn retract_all(&self, head: &Term) -> Result<(), Error> {
+        retract_all(self.db, &self.store, head)
+    }
+
+    pub fn query(
+        &self,
+        query_node: &Term,
+        env: &mut HashMap<String, Term>,
+    ) -> Result<Option<HashMap<String, Term>>, Error> {
+        query(self.db, &self.store, query_node, env)
+    }
+
+    pub fn query_all(&self, query_node: &Term) -> Result<Vec<HashMap<String, Term>>, Error> {
+        query_all(self.db, &self.store, query_node)
+    }
+}
+
+fn query(
+    db: &DB,
+    store: &RefCell<Store>,
+    query_node: &Term,
+    env: &mut HashMap<String, Term>,
+) -> Result<Option<HashMap<String, Term>>, Error> {
+    let mut solver = Solver::new(db, store, Rc::default(), env.clone(), query_node.clone());
+    if !solver.next_solution().map_err(Error::from_ball)? {
+        return Ok(None);
+    }
+
+    let vars = solver.answer(query_node);
+    for (key, val) in vars.iter() {
+        env.insert(key.clone(), val.clone());
+    }
+    Ok(Some(vars))
+}
+
+fn query_all(
+    db: &DB,
+    store: &RefCell<Store>,
+    query_node: &Term,
+) -> Result<Vec<HashMap<String, Term>>, Error> {
+    let mut solver = Solver::new(db, store, Rc::default(), HashMap::new(), query_node.clone());
+    let mut answers = vec![];
+    while solver.next_solution().map_err(Error::from_ball)? {
+        answers.push(solver.answer(query_node));
+    }
+    Ok(answers)
+}
+
+fn retract(db: &DB, store: &RefCell<Store>, clause: &Term) -> Result<bool, Error> {
+    let goal = Term::CompoundTerm("retract".to_string(), vec![clause.clone()]);
+    Ok(query(db, store, &goal, &mut HashMap::new())?.is_some())
+}
+
+fn retract_all(db: &DB, store: &RefCell<Store>, head: &Term) -> Result<(), Error> {
+    let goal = Term::CompoundTerm("retractall".to_string(), vec![head.clone()]);
+    query(db, store, &goal, &mut HashMap::new())?;
+    Ok(())
+}
+
+fn conjuncts(value: &Term) -> Vec<&Term> {
+    match value {
+        Term::CompoundTerm(name, args) if name == "," && args.len() == 2 => {
+            let mut values = conjuncts(&args[0]);
+            values.extend(conjuncts(&args[1]));
+            values
+        }
+        _ => vec![value],
+    }
+}
+
+fn cut_body(goal: Term, height: usize) -> Term {
+    match goal {
+        Term::CompoundTerm(name, args) if name == "!" && args.is_empty() => {
+            Term::CompoundTerm("$cut".to_string(), vec![Term::Int(height)])
+        }
+        Term::CompoundTerm(name, mut args)
+            if args.len() == 2 && [",", ";", "->"].contains(&name.as_str()) =>
+        {
+            let right = cut_body(args.pop().unwrap(), height);
+            let left = match args.pop().unwrap() {
+                condition if name == "->" => condition,
+                left => cut_body(left, height),
+            };
+            Term::CompoundTerm(name, vec![left, right])
+        }
+        goal => goal,
+    }
+}
+
+fn invalid_data(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn predicate_indicators(spec: &Term) -> Result<Vec<(String, usize)>, Term> {
+    let specs = match spec {
+        Term::List(specs) => specs.iter().collect(),
+        _ => conjuncts(spec),
+    };
+    specs.into_iter().map(predicate_indicator).collect()
+}
+
+fn predicate_indicator(spec: &Term) -> Result<(String, usize), Term> {
+    match spec {
+        Term::Variable(_) => Err(error::instantiation_error()),
+        Term::CompoundTerm(slash, args) if slash == "/" && args.len() == 2 => {
+            match (&args[0], &args[1]) {
+                (Term::Variable(_), _) | (_, Term::Variable(_)) => {
+                    Err(error::instantiation_error())
                 }
+                (Term::CompoundTerm(name, none), Term::Int(arity)) if none.is_empty() => {
+                    Ok((name.clone(), *arity))
+                }
+                _ => Err(error::type_error("predicate_indicator", spec.clone())),
+            }
+        }
+        _ => Err(error::type_error("predicate_indicator", spec.clone())),
+    }
+}
 
-                for (lhs, rhs) in args_a.iter().zip(args_b) {
-                    if !self.unify(lhs, rhs, map) {
-                        return false;
+#[derive(Debug, Default)]
+struct Table {
+    answers: Vec<Term>,
+    seen: HashSet<Term>,
+    complete: bool,
+    evaluating: bool,
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    tables: HashMap<Term, Table>,
+    created: Vec<Term>,
+    stack: Vec<usize>,
+    answers_added: usize,
+}
+
+struct Goals {
+    goal: Term,
+    next: Option<Rc<Goals>>,
+}
+
+enum Alternatives {
+    Clauses {
+        goal: Term,
+        clauses: Clauses,
+        next: usize,
+    },
+    Bodies {
+        goal: Clause,
+        clauses: Clauses,
+        next: usize,
+        retract: bool,
+    },
+    Answers {
+        goal: Term,
+        answers: Vec<Term>,
+        next: usize,
+    },
+    Catch {
+        catcher: Term,
+        recovery: Term,
+        exited: String,
+    },
+    Goal(Option<Term>),
+    Members {
+        goal: Term,
+        values: Vec<Term>,
+        next: usize,
+    },
+    Lengths {
+        prefix: usize,
+        tail: Term,
+        length: Term,
+        next: usize,
+    },
+}
+
+struct ChoicePoint {
+    trail_len: usize,
+    goals: Option<Rc<Goals>>,
+    alternatives: Alternatives,
+}
+
+struct Solver<'a> {
+    db: &'a DB,
+    store: &'a RefCell<Store>,
+    tables: Rc<RefCell<Tables>>,
+    bindings: HashMap<String, Term>,
+    trail: Vec<String>,
+    goals: Option<Rc<Goals>>,
+    choicepoints: Vec<ChoicePoint>,
+    renames: usize,
+    started: bool,
+    exception: Option<Term>,
+}
+
+impl<'a> Solver<'a> {
+    fn new(
+        db: &'a DB,
+        store: &'a RefCell<Store>,
+        tables: Rc<RefCell<Tables>>,
+        bindings: HashMap<String, Term>,
+        goal: Term,
+    ) -> Solver<'a> {
+        Solver {
+            db,
+            store,
+            tables,
+            bindings,
+            trail: vec![],
+            goals: Some(Rc::new(Goals {
+                goal: cut_body(goal, 0),
+                next: None,
+            })),
+            choicepoints: vec![],
+            renames: 0,
+            started: false,
+            exception: None,
+        }
+    }
+
+    fn for_clauses(
+        db: &'a DB,
+        store: &'a RefCell<Store>,
+        tables: Rc<RefCell<Tables>>,
+        goal: Term,
+    ) -> Solver<'a> {
+        let key = match &goal {
+            Term::CompoundTerm(name, args) => (name.clone(), args.len()),
+            _ => unreachable!(),
+        };
+        let mut solver = Solver::new(db, store, tables, HashMap::new(), goal.clone());
+        solver.goals = None;
+        solver.choicepoints.push(ChoicePoint {
+            trail_len: 0,
+            goals: None,
+            alternatives: Alternatives::Clauses {
+                clauses: store.borrow().clauses(&key),
+                goal,
+                next: 0,
+            },
+        });
+        solver.started = true;
+        solver
+    }
+
+    fn next_solution(&mut self) -> Result<bool, Term> {
+        if self.started && !self.backtrack() {
+            return Ok(false);
+        }
+        self.started = true;
+
+        loop {
+            let Some(frame) = self.goals.take() else {
+                return Ok(true);
+            };
+            self.goals = frame.next.clone();
+            if self.step(&frame.goal) {
+                continue;
+            }
+            match self.exception.take() {
+                Some(ball) => self.recover(ball)?,
+                None if !self.backtrack() => return Ok(false),
+                None => (),
+            }
+        }
+    }
+
+    fn throw(&mut self, ball: Term) -> bool {
+        self.exception = Some(ball);
+        false
+    }
+
+    fn error(&mut self, formal: Term) -> bool {
+        let context = Term::Variable(format!("_#{}", self.fresh_suffix()));
+        self.throw(Term::CompoundTerm(
+            "error".to_string(),
+            vec![formal, context],
+        ))
+    }
+
+    fn recover(&mut self, ball: Term) -> Result<(), Term> {
+        while let Some(choicepoint) = self.choicepoints.pop() {
+            let Alternatives::Catch {
+                catcher,
+                recovery,
+                exited,
+            } = choicepoint.alternatives
+            else {
+                continue;
+            };
+            if self.bindings.contains_key(&exited) {
+                continue;
+            }
+
+            self.undo(choicepoint.trail_len);
+            self.goals = choicepoint.goals;
+            if self.unify(&catcher, &ball) {
+                self.push_goal(recovery);
+                return Ok(());
+            }
+            self.undo(choicepoint.trail_len);
+        }
+        Err(ball)
+    }
+
+    fn answer(&self, query: &Term) -> HashMap<String, Term> {
+        let mut names = vec![];
+        query.collect_vars(&mut names);
+        names
+            .into_iter()
+            .map(|name| {
+                let val = self.resolve(&Term::Variable(name.clone()));
+                (name, val)
+            })
+            .collect()
+    }
+
+    fn push_cut(&mut self, height: usize) {
+        self.push_goal(Term::CompoundTerm(
+            "$cut".to_string(),
+            vec![Term::Int(height)],
+        ));
+    }
+
+    fn push_goal(&mut self, goal: Term) {
+        self.goals = Some(Rc::new(Goals {
+            goal,
+            next: self.goals.take(),
+        }));
+    }
+
+    fn step(&mut self, goal: &Term) -> bool {
+        match goal {
+            Term::CompoundTerm(name, args) => match (name.as_str(), args.as_slice()) {
+                (",", [left, right]) => {
+                    self.push_goal(right.clone());
+                    self.push_goal(left.clone());
+                    true
+                }
+                ("=", [left, right]) => self.unify(left, right),
+                (">" | "<" | ">=" | "<=", [left, right]) => self.compare(name, left, right),
+                ("true" | "!", []) => true,
+                ("fail", []) => false,
+                (";", [left, right]) => {
+                    let height = self.choicepoints.len();
+                    self.choicepoints.push(ChoicePoint {
+                        trail_len: self.trail.len(),
+                        goals: self.goals.clone(),
+                        alternatives: Alternatives::Goal(Some(right.clone())),
+                    });
+                    match left {
+                        Term::CompoundTerm(name, args) if name == "->" && args.len() == 2 => {
+                            self.push_goal(args[1].clone());
+                            self.push_cut(height);
+                            self.push_goal(cut_body(args[0].clone(), height + 1));
+                        }
+                        _ => self.push_goal(left.clone()),
                     }
+                    true
                 }
-                true
-            }
-            (Value::Str(_), Value::CompoundTerm(_, _)) => todo!(),
-            (Value::Str(a), Value::Str(b)) => a == b,
-            (Value::Str(_), Value::Int(_)) => false,
-            (Value::Int(_), Value::CompoundTerm(_, _)) => todo!(),
-            (Value::Int(_), Value::Str(_)) => false,
-            (Value::Int(a), Value::Int(b)) => a == b,
-            (Value::Variable(a), Value::Variable(b)) => match (map.get(a), map.get(b)) {
-                (None, None) => {
-                    map.insert(a.clone(), Value::Variable(b.clone()));
+                ("->", [condition, then]) => {
+                    let height = self.choicepoints.len();
+                    self.push_goal(then.clone());
+                    self.push_cut(height);
+                    self.push_goal(cut_body(condition.clone(), height));
                     true
-                } // this will fail at some point
-                (None, Some(value)) => {
-                    map.insert(a.clone(), value.clone());
+                }
+                ("throw", [ball]) => match self.resolve(ball) {
+                    Term::Variable(_) => self.error(error::instantiation_error()),
+                    ball => self.throw(ball),
+                },
+                ("catch", [goal, catcher, recovery]) => {
+                    let exited = format!("$catch#{}", self.fresh_suffix());
+                    self.choicepoints.push(ChoicePoint {
+                        trail_len: self.trail.len(),
+                        goals: self.goals.clone(),
+                        alternatives: Alternatives::Catch {
+                            catcher: catcher.clone(),
+                            recovery: recovery.clone(),
+                            exited: exited.clone(),
+                        },
+                    });
+                    self.push_goal(Term::CompoundTerm(
+                        "$exit_catch".to_string(),
+                        vec![Term::Variable(exited)],
+                    ));
+                    self.push_goal(cut_body(goal.clone(), self.choicepoints.len()));
                     true
                 }
-                (Some(value), None) => {
-                    map.insert(b.clone(), value.clone());
+                ("$exit_catch", [exited]) => self.unify(exited, &error::atom("true")),
+                ("call", [goal, extra @ ..]) if extra.len() <= 7 => match self.resolve(goal) {
+                    Term::Variable(_) => self.error(error::instantiation_error()),
+                    goal if extra.is_empty() => {
+                        self.push_goal(cut_body(goal, self.choicepoints.len()));
+                        true
+                    }
+                    Term::CompoundTerm(name, mut args) => {
+                        args.extend(extra.iter().cloned());
+                        let goal = Term::CompoundTerm(name, args);
+                        self.push_goal(cut_body(goal, self.choicepoints.len()));
+                        true
+                    }
+                    goal => self.error(error::type_error("callable", goal)),
+                },
+                ("once", [goal]) => {
+                    let height = self.choicepoints.len();
+                    self.push_cut(height);
+                    self.push_goal(cut_body(goal.clone(), height));
                     true
                 }
-                (Some(a), Some(b)) => a == b,
-            },
-            (Value::Variable(name), rhs) => {
-                if let Some(val) = map.get(name) {
-                    rhs == val
-                } else {
-                    map.insert(name.to_owned(), rhs.to_owned());
+                ("ignore", [goal]) => {
+                    let height = self.choicepoints.len();
+                    self.choicepoints.push(ChoicePoint {
+                        trail_len: self.trail.len(),
+                        goals: self.goals.clone(),
+                        alternatives: Alternatives::Goal(Some(error::atom("true"))),
+                    });
+                    self.push_cut(height);
+                    self.push_goal(cut_body(goal.clone(), height + 1));
                     true
                 }
-            }
-            (_, Value::Variable(name)) => {
-                if let Some(val) = map.get(name) {
-                    query == val
-                } else {
-                    map.insert(name.to_owned(), query.to_owned());
+                ("$cut", [Term::Int(height)]) => {
+                    self.choicepoints.truncate(*height);
                     true
                 }
-            }
-            (Value::Predicate(..), _) => todo!(),
-            (
-                Value::CompoundTerm(query_name, query_args),
-                Value::Predicate(pred_name, pred_args, pred_body),
-            ) => {
-                if query_name != pred_name {
-                    return false;
+                ("var", [value]) => matches!(self.deref(value), Term::Variable(_)),
+                ("nonvar", [value]) => !matches!(self.deref(value), Term::Variable(_)),
+                ("number" | "integer", [value]) => matches!(self.deref(value), Term::Int(_)),
+                ("string", [value]) => matches!(self.deref(value), Term::Str(_)),
+                ("atom", [value]) => {
+                    matches!(term_parts(&self.deref(value)), Some((_, args)) if args.is_empty())
                 }
-                if query_args.len() != pred_args.len() {
-                    return false;
+                ("compound", [value]) => {
+                    matches!(term_parts(&self.deref(value)), Some((_, args)) if !args.is_empty())
                 }
-
-                for (query_arg, pred_arg) in query_args.iter().zip(pred_args) {
-                    if !self.unify(query_arg, pred_arg, map) {
-                        return false;
-                    }
+                ("atomic", [value]) => match self.deref(value) {
+                    Term::Variable(_) => false,
+                    value => !matches!(term_parts(&value), Some((_, args)) if !args.is_empty()),
+                },
+                ("callable", [value]) => term_parts(&self.deref(value)).is_some(),
+                ("is_list", [value]) => matches!(self.list_parts(value), Some((_, None))),
+                ("ground", [value]) => {
+                    let mut names = vec![];
+                    self.resolve(value).collect_vars(&mut names);
+                    names.is_empty()
                 }
-
-                let mut scope = map.clone();
-                match pred_body.as_ref() {
-                    Value::CompoundTerm(_, _) => {
-                        if self.query(pred_body, &mut scope).is_some() {
-                            for name in query_args.iter().filter_map(|n| match n {
-                                Value::Variable(name) => Some(name),
-                                _ => None,
-                            }) {
-                                map.insert(name.clone(), scope.get(name).unwrap().clone());
-                            }
-                            true
-                        } else {
-                            false
+                ("functor", [term, name, arity]) => self.functor(term, name, arity),
+                ("arg", [index, term, arg]) => self.arg(index, term, arg),
+                ("=..", [term, list]) => self.univ(term, list),
+                ("copy_term", [term, copy]) => {
+                    let suffix = self.fresh_suffix();
+                    let term = rename(&self.resolve(term), &suffix);
+                    self.unify(copy, &term)
+                }
+                ("compare", [order, left, right]) => {
+                    let expected = match compare_terms(&self.resolve(left), &self.resolve(right)) {
+                        Ordering::Less => "<",
+                        Ordering::Equal => "=",
+                        Ordering::Greater => ">",
+                    };
+                    match self.deref(order) {
+                        Term::Variable(_) => (),
+                        Term::CompoundTerm(name, args)
+                            if args.is_empty() && ["<", "=", ">"].contains(&name.as_str()) => {}
+                        order @ Term::CompoundTerm(_, _) => {
+                            return self.error(error::domain_error("order", order))
                         }
+                        order => return self.error(error::type_error("atom", order)),
                     }
-                    Value::LessThan(left, right) => {
-                        match (left.as_ref(), right.as_ref()) {
-                            (Value::Variable(_), Value::Variable(_)) => {
-                                panic!("can't compare variables")
-                            }
-                            (Value::Int(left), Value::Int(right)) => left < right,
-                            (Value::Variable(name), Value::Int(right)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(left) = value {
-                                        left < right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    map.insert(name.clone(), Value::Int(right - 1));
-                                    true
-                                }
-                            }
-                            (Value::Int(left), Value::Variable(name)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(right) = value {
-                                        left < right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    scope.insert(name.clone(), Value::Int(left + 1));
-                                    true
-                                }
-                            }
-                            _ => panic!("unknown"),
+                    self.unify(order, &error::atom(expected))
+                }
+                ("==" | "\\==" | "@<" | "@>" | "@=<" | "@>=", [left, right]) => {
+                    let order = compare_terms(&self.resolve(left), &self.resolve(right));
+                    match name.as_str() {
+                        "==" => order.is_eq(),
+                        "\\==" => order.is_ne(),
+                        "@<" => order.is_lt(),
+                        "@>" => order.is_gt(),
+                        "@=<" => order.is_le(),
+                        _ => order.is_ge(),
+                    }
+                }
+                ("sort", [list, sorted]) => match self.proper_list(list) {
+                    Ok(mut values) => {
+                        sort_terms(&mut values);
+                        self.unify(sorted, &Term::List(values))
+                    }
+                    Err(formal) => self.error(formal),
+                },
+                ("keysort", [pairs, sorted]) => self.keysort(pairs, sorted),
+                ("atom_length", [atom, length]) => {
+                    let result = self.atom_length(atom, length);
+                    self.or_error(result)
+                }
+                ("atom_concat", [left, right, whole]) => {
+                    let result = self.concat(left, right, whole, atom_value);
+                    self.or_error(result)
+                }
+                ("string_concat", [left, right, whole]) => {
+                    let result = self.concat(left, right, whole, Term::Str);
+                    self.or_error(result)
+                }
+                ("sub_atom", [atom, before, length, after, sub]) => {
+                    let result = self.sub_atom(atom, before, length, after, sub);
+                    self.or_error(result)
+                }
+                ("split_string", [string, separators, pad, parts]) => {
+                    let result = self.split_string(string, separators, pad, parts);
+                    self.or_error(result)
+                }
+                ("atom_string", [atom, string]) => {
+                    let result = self.atom_string(atom, string);
+                    self.or_error(result)
+                }
+                ("number_codes", [number, codes]) => {
+                    let result = self.number_codes(number, codes);
+                    self.or_error(result)
+                }
+                ("atom_chars", [atom, chars]) => {
+                    let result = self.atom_chars(atom, chars);
+                    self.or_error(result)
+                }
+                ("upcase_atom", [atom, upper]) => {
+                    let result = self
+                        .bound_text(atom)
+                        .map(|text| self.unify(upper, &atom_value(text.to_uppercase())));
+                    self.or_error(result)
+                }
+                ("string_code", [index, string, code]) => {
+                    let result = self.string_code(index, string, code);
+                    self.or_error(result)
+                }
+                ("length", [list, length]) => self.length(list, length),
+                ("nth0", [index, list, elem]) => self.nth(index, list, elem, 0),
+                ("nth1", [index, list, elem]) => self.nth(index, list, elem, 1),
+                ("msort", [list, sorted]) => match self.proper_list(list) {
+                    Ok(mut values) => {
+                        values.sort_by(compare_terms);
+                        self.unify(sorted, &Term::List(values))
+                    }
+                    Err(formal) => self.error(formal),
+                },
+                ("sort", [key, order, list, sorted]) => self.sort(key, order, list, sorted),
+                ("not" | "\\+", [goal]) => match self.succeeds(goal) {
+                    Ok(succeeded) => !succeeded,
+                    Err(ball) => self.throw(ball),
+                },
+                ("forall", [condition, action]) => {
+                    let not = |goal| Term::CompoundTerm("not".to_string(), vec![goal]);
+                    let counterexample = Term::CompoundTerm(
+                        ",".to_string(),
+                        vec![condition.clone(), not(action.clone())],
+                    );
+                    self.push_goal(not(counterexample));
+                    true
+                }
+                ("assert" | "assertz", [clause]) => match self.assertable(clause) {
+                    Some(clause) => self.store.borrow_mut().assertz(clause).is_ok(),
+                    None => false,
+                },
+                ("asserta", [clause]) => match self.assertable(clause) {
+                    Some(clause) => self.store.borrow_mut().asserta(clause).is_ok(),
+                    None => false,
+                },
+                ("retract", [clause]) => {
+                    let Some((clause, key)) = self.clause(clause) else {
+                        return false;
+                    };
+                    self.call(Alternatives::Bodies {
+                        clauses: self.clauses(&key),
+                        goal: clause,
+                        next: 0,
+                        retract: true,
+                    })
+                }
+                ("clause", [head, body]) => {
+                    let Some((Clause { head, .. }, key)) = self.clause(head) else {
+                        return false;
+                    };
+                    match self.resolve(body) {
+                        body @ (Term::Variable(_) | Term::CompoundTerm(_, _)) => {
+                            let goal = Clause { head, body };
+                            self.call(Alternatives::Bodies {
+                                clauses: self.clauses(&key),
+                                goal,
+                                next: 0,
+                                retract: false,
+                            })
                         }
+                        body => self.error(error::type_error("callable", body)),
                     }
-                    Value::Predicate(_, _, _) => todo!(),
-                    Value::List(_) => todo!(),
-                    Value::Str(_) => todo!(),
-                    Value::Int(_) => todo!(),
-                    Value::Variable(_) => todo!(),
-                    Value::Eq(_, _) => todo!(),
-                    Value::And(_, _) => todo!(),
-                    Value::GreaterThan(left, right) => {
-                        match (left.as_ref(), right.as_ref()) {
-                            (Value::Variable(_), Value::Variable(_)) => {
-                                panic!("can't compare variables")
-                            }
-                            (Value::Int(left), Value::Int(right)) => left > right,
-                            (Value::Variable(name), Value::Int(right)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(left) = value {
-                                        left > right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    map.insert(name.clone(), Value::Int(right + 1));
-                                    true
-                                }
-                            }
-                            (Value::Int(left), Value::Variable(name)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(right) = value {
-                                        left > right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    scope.insert(name.clone(), Value::Int(left - 1));
-                                    true
-                                }
-                            }
-                            _ => panic!("unknown"),
+                }
+                ("retractall", [head]) => {
+                    let Some((goal, key)) = self.clause(head) else {
+                        return false;
+                    };
+                    for clause in self.clauses(&key).clauses.iter() {
+                        let trail_len = self.trail.len();
+                        let renamed = self.rename_clause(clause);
+                        if self.unify(&goal.head, &renamed.head) {
+                            self.store.borrow_mut().remove(clause);
                         }
+                        self.undo(trail_len);
                     }
-                    Value::GreaterThanEqual(left, right) => {
-                        match (left.as_ref(), right.as_ref()) {
-                            (Value::Variable(_), Value::Variable(_)) => {
-                                panic!("can't compare variables")
-                            }
-                            (Value::Int(left), Value::Int(right)) => left >= right,
-                            (Value::Variable(name), Value::Int(right)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(left) = value {
-                                        left >= right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    map.insert(name.clone(), Value::Int(*right));
-                                    true
-                                }
-                            }
-                            (Value::Int(left), Value::Variable(name)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(right) = value {
-                                        left >= right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    scope.insert(name.clone(), Value::Int(*left));
-                                    true
-                                }
-                            }
-                            _ => panic!("unknown"),
+                    true
+                }
+                ("transaction", [goal]) => self.transaction(goal),
+                ("^", [_, goal]) => {
+                    self.push_goal(cut_body(goal.clone(), self.choicepoints.len()));
+                    true
+                }
+                ("findall", [template, goal, result]) => match self.solutions(template, goal) {
+                    Ok(results) => self.unify(result, &Term::List(results)),
+                    Err(ball) => self.throw(ball),
+                },
+                ("bagof", [template, goal, result]) => self.bagof(template, goal, result, false),
+                ("setof", [template, goal, result]) => self.bagof(template, goal, result, true),
+                ("aggregate_all", [spec, goal, result]) => self.aggregate_all(spec, goal, result),
+                ("dynamic", [spec]) => match predicate_indicators(&self.resolve(spec)) {
+                    Ok(keys) => {
+                        for key in keys {
+                            self.store.borrow_mut().declare(key);
                         }
+                        true
                     }
-                    Value::LessThanEqual(left, right) => {
-                        match (left.as_ref(), right.as_ref()) {
-                            (Value::Variable(_), Value::Variable(_)) => {
-                                panic!("can't compare variables")
-                            }
-                            (Value::Int(left), Value::Int(right)) => left <= right,
-                            (Value::Variable(name), Value::Int(right)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(left) = value {
-                                        left <= right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    map.insert(name.clone(), Value::Int(*right));
-                                    true
-                                }
-                            }
-                            (Value::Int(left), Value::Variable(name)) => {
-                                if let Some(value) = scope.get(name) {
-                                    if let Value::Int(right) = value {
-                                        left <= right
-                                    } else {
-                                        panic!("var not int");
-                                    }
-                                } else {
-                                    // TODO: use random number generator
-                                    scope.insert(name.clone(), Value::Int(*left));
-                                    true
-                                }
+                    Err(formal) => self.error(formal),
+                },
+                ("set_prolog_flag", [flag, value]) => {
+                    match self.db.set_flag(&self.resolve(flag), &self.resolve(value)) {
+                        Ok(()) => true,
+                        Err(formal) => self.error(formal),
+                    }
+                }
+                ("current_prolog_flag", [flag, value]) => {
+                    let unknown = error::atom(self.db.unknown.get().name());
+                    self.unify(flag, &error::atom("unknown")) && self.unify(value, &unknown)
+                }
+                _ => {
+                    let key = (name.clone(), args.len());
+                    if !self.store.borrow().predicates.contains_key(&key) {
+                        match self.db.unknown.get() {
+                            Unknown::Error => self.error(error::existence_error(name, args.len())),
+                            Unknown::Warning => {
+                                eprintln!("Warning: unknown procedure {}/{}", name, args.len());
+                                false
                             }
-                            _ => panic!("unknown"),
+                            Unknown::Fail => false,
                         }
+                    } else if self.db.tabled.contains(&key) {
+                        self.call_tabled(goal)
+                    } else {
+                        self.call(Alternatives::Clauses {
+                            goal: goal.clone(),
+                            clauses: self.clauses(&key),
+                            next: 0,
+                        })
+                    }
+                }
+            },
+            Term::Variable(_) => match self.deref(goal) {
+                Term::Variable(_) => self.error(error::instantiation_error()),
+                goal => {
+                    self.push_goal(cut_body(goal, self.choicepoints.len()));
+                    true
+                }
+            },
+            _ => self.error(error::type_error("callable", goal.clone())),
+        }
+    }
+
+    fn clause(&mut self, clause: &Term) -> Option<(Clause, (String, usize))> {
+        let clause = Clause::from_term(self.resolve(clause));
+        match clause.key() {
+            Ok(key) => Some((clause, key)),
+            Err(Error::Instantiation) => {
+                self.error(error::instantiation_error());
+                None
+            }
+            Err(_) => {
+                self.error(error::type_error("callable", clause.head));
+                None
+            }
+        }
+    }
+
+    fn assertable(&mut self, clause: &Term) -> Option<Clause> {
+        let (clause, _) = self.clause(clause)?;
+        match clause.body {
+            Term::Variable(_) | Term::CompoundTerm(_, _) => Some(clause),
+            body => {
+                self.error(error::type_error("callable", body));
+                None
+            }
+        }
+    }
+
+    fn call(&mut self, alternatives: Alternatives) -> bool {
+        let mut choicepoint = ChoicePoint {
+            trail_len: self.trail.len(),
+            goals: self.goals.clone(),
+            alternatives,
+        };
+        self.resume(&mut choicepoint)
+    }
+
+    fn backtrack(&mut self) -> bool {
+        while let Some(mut choicepoint) = self.choicepoints.pop() {
+            self.undo(choicepoint.trail_len);
+            self.goals = choicepoint.goals.clone();
+            if self.resume(&mut choicepoint) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn resume(&mut self, choicepoint: &mut ChoicePoint) -> bool {
+        match &mut choicepoint.alternatives {
+            Alternatives::Clauses {
+                goal,
+                clauses: predicate,
+                next,
+            } => {
+                let height = self.choicepoints.len();
+                let key = match &*goal {
+                    Term::CompoundTerm(_, args) => {
+                        args.first().and_then(|arg| index_key(&self.deref(arg)))
+                    }
+                    _ => unreachable!(),
+                };
+                let candidates = predicate.candidates(key.as_ref());
+                let len = candidates.map_or(predicate.clauses.len(), |c| c.len());
+
+                while *next < len {
+                    let clause = &predicate.clauses[candidates.map_or(*next, |c| c[*next])];
+                    *next += 1;
+
+                    let clause = self.rename_clause(clause);
+                    if self.unify(goal, &clause.head) {
+                        if *next < len {
+                            self.choicepoints.push(ChoicePoint {
+                                trail_len: choicepoint.trail_len,
+                                goals: choicepoint.goals.clone(),
+                                alternatives: Alternatives::Clauses {
+                                    goal: goal.clone(),
+                                    clauses: predicate.clone(),
+                                    next: *next,
+                                },
+                            });
+                        }
+                        if !clause.is_fact() {
+                            self.push_goal(cut_body(clause.body, height));
+                        }
+                        return true;
+                    }
+                    self.undo(choicepoint.trail_len);
+                }
+                false
+            }
+            Alternatives::Bodies {
+                goal,
+                clauses: predicate,
+                next,
+                retract,
+            } => {
+                while *next < predicate.clauses.len() {
+                    let clause = predicate.clauses[*next].clone();
+                    *next += 1;
+                    let renamed = self.rename_clause(&clause);
+                    if self.unify(&goal.head, &renamed.head)
+                        && self.unify(&goal.body, &renamed.body)
+                        && (!*retract || self.store.borrow_mut().remove(&clause))
+                    {
+                        if *next < predicate.clauses.len() {
+                            self.choicepoints.push(ChoicePoint {
+                                trail_len: choicepoint.trail_len,
+                                goals: choicepoint.goals.clone(),
+                                alternatives: Alternatives::Bodies {
+                                    goal: goal.clone(),
+                                    clauses: predicate.clone(),
+                                    next: *next,
+                                    retract: *retract,
+                                },
+                            });
+                        }
+                        return true;
+                    }
+                    self.undo(choicepoint.trail_len);
+                }
+                false
+            }
+            Alternatives::Catch { .. } => false,
+            Alternatives::Members { goal, values, next } => {
+                while *next < values.len() {
+                    let value = values[*next].clone();
+                    *next += 1;
+                    if self.unify(goal, &value) {
+                        if *next < values.len() {
+                            self.choicepoints.push(ChoicePoint {
+                                trail_len: choicepoint.trail_len,
+                                goals: choicepoint.goals.clone(),
+                                alternatives: Alternatives::Members {
+                                    goal: goal.clone(),
+                                    values: std::mem::take(values),
+                                    next: *next,
+                                },
+                            });
+                        }
+                        return true;
+                    }
+                    self.undo(choicepoint.trail_len);
+                }
+                false
+            }
+            Alternatives::Lengths {
+                prefix,
+                tail,
+                length,
+                next,
+            } => {
+                let suffix = self.fresh_suffix();
+                let fresh = (0..*next)
+                    .map(|idx| Term::Variable(format!("_{}#{}", idx, suffix)))
+                    .collect();
+                self.choicepoints.push(ChoicePoint {
+                    trail_len: choicepoint.trail_len,
+                    goals: choicepoint.goals.clone(),
+                    alternatives: Alternatives::Lengths {
+                        prefix: *prefix,
+                        tail: tail.clone(),
+                        length: length.clone(),
+                        next: *next + 1,
+                    },
+                });
+                self.unify(tail, &Term::List(fresh))
+                    && self.unify(length, &Term::Int(*prefix + *next))
+            }
+            Alternatives::Goal(goal) => match goal.take() {
+                Some(goal) => {
+                    self.push_goal(goal);
+                    true
+                }
+                None => false,
+            },
+            Alternatives::Answers {
+                goal,
+                answers,
+                next,
+            } => {
+                while *next < answers.len() {
+                    let answer = rename(&answers[*next], &self.fresh_suffix());
+                    *next += 1;
+                    if self.unify(goal, &answer) {
+                        if *next < answers.len() {
+                            self.choicepoints.push(ChoicePoint {
+                                trail_len: choicepoint.trail_len,
+                                goals: choicepoint.goals.clone(),
+                                alternatives: Alternatives::Answers {
+                                    goal: goal.clone(),
+                                    answers: std::mem::take(answers),
+                                    next: *next,
+                                },
+                            });
+                        }
+                        return true;
+                    }
+                    self.undo(choicepoint.trail_len);
+                }
+                false
+            }
+        }
+    }
+
+    fn clauses(&self, key: &(String, usize)) -> Clauses {
+        self.store.borrow().clauses(key)
+    }
+
+    fn transaction(&mut self, goal: &Term) -> bool {
+        let goal = self.resolve(goal);
+        let store = RefCell::new(self.store.borrow().clone());
+        let mut solver = Solver::new(self.db, &store, Rc::default(), HashMap::new(), goal.clone());
+        solver.renames = self.renames;
+        let solved = solver.next_solution();
+        let result = solver.resolve(&goal);
+        self.renames = solver.renames;
+        drop(solver);
+
+        match solved {
+            Ok(true) => {
+                self.store.replace(store.into_inner());
+                if std::ptr::eq(self.store, &self.db.store) {
+                    if let Err(err) = self.db.flush() {
+                        return self.error(error::io_error(&err.to_string()));
+                    }
+                }
+                self.unify(&goal, &result)
+            }
+            Ok(false) => false,
+            Err(ball) => self.throw(ball),
+        }
+    }
+
+    fn list_parts(&self, list: &Term) -> Option<(Vec<Term>, Option<Term>)> {
+        let mut values = vec![];
+        let mut list = self.deref(list);
+        loop {
+            match list {
+                Term::List(rest) => {
+                    values.extend(rest);
+                    return Some((values, None));
+                }
+                Term::Cons(head, tail) => {
+                    values.push(*head);
+                    list = self.deref(&tail);
+                }
+                tail @ Term::Variable(_) => return Some((values, Some(tail))),
+                _ => return None,
+            }
+        }
+    }
+
+    fn proper_list(&self, list: &Term) -> Result<Vec<Term>, Term> {
+        match self.list_parts(list) {
+            Some((values, None)) => Ok(values.iter().map(|value| self.resolve(value)).collect()),
+            Some((_, Some(_))) => Err(error::instantiation_error()),
+            None => Err(error::type_error("list", self.resolve(list))),
+        }
+    }
+
+    fn functor(&mut self, term: &Term, name: &Term, arity: &Term) -> bool {
+        let term = self.deref(term);
+        if let Some((functor, args)) = term_parts(&term) {
+            return self.unify(name, &make_term(functor, vec![]))
+                && self.unify(arity, &Term::Int(args.len()));
+        }
+        if !matches!(term, Term::Variable(_)) {
+            return self.unify(name, &term) && self.unify(arity, &Term::Int(0));
+        }
+
+        let arity = match self.deref(arity) {
+            Term::Int(arity) => arity,
+            Term::Variable(_) => return self.error(error::instantiation_error()),
+            culprit => return self.error(error::type_error("integer", culprit)),
+        };
+        let built = match (self.deref(name), term_parts(&self.deref(name))) {
+            (Term::Variable(_), _) => return self.error(error::instantiation_error()),
+            (_, Some((_, args))) if !args.is_empty() => {
+                return self.error(error::type_error("atomic", self.resolve(name)))
+            }
+            (name, _) if arity == 0 => name,
+            (_, Some((functor, _))) => {
+                let suffix = self.fresh_suffix();
+                let args = (0..arity)
+                    .map(|idx| Term::Variable(format!("_{}#{}", idx, suffix)))
+                    .collect();
+                make_term(functor, args)
+            }
+            (name, None) => return self.error(error::type_error("atom", name)),
+        };
+        self.unify(&term, &built)
+    }
+
+    fn arg(&mut self, index: &Term, term: &Term, arg: &Term) -> bool {
+        let args = match (self.deref(term), term_parts(&self.deref(term))) {
+            (Term::Variable(_), _) => return self.error(error::instantiation_error()),
+            (_, Some((_, args))) if !args.is_empty() => args,
+            (term, _) => return self.error(error::type_error("compound", term)),
+        };
+        match self.deref(index) {
+            Term::Int(index) => match index.checked_sub(1).and_then(|idx| args.get(idx)) {
+                Some(value) => self.unify(arg, value),
+                None => false,
+            },
+            index @ Term::Variable(_) => {
+                let values = args
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, value)| {
+                        Term::CompoundTerm("-".to_string(), vec![Term::Int(idx + 1), value])
+                    })
+                    .collect();
+                self.call(Alternatives::Members {
+                    goal: Term::CompoundTerm("-".to_string(), vec![index, arg.clone()]),
+                    values,
+                    next: 0,
+                })
+            }
+            culprit => self.error(error::type_error("integer", culprit)),
+        }
+    }
+
+    fn univ(&mut self, term: &Term, list: &Term) -> bool {
+        match self.deref(term) {
+            Term::Variable(_) => (),
+            term => {
+                let parts = match term_parts(&term) {
+                    Some((name, args)) => [vec![make_term(name, vec![])], args].concat(),
+                    None => vec![term],
+                };
+                return self.unify(list, &Term::List(parts));
+            }
+        }
+
+        let mut parts = match self.proper_list(list) {
+            Ok(parts) => parts.into_iter(),
+            Err(formal) => return self.error(formal),
+        };
+        let built = match parts.next().map(|head| self.deref(&head)) {
+            None => return self.error(error::domain_error("non_empty_list", Term::List(vec![]))),
+            Some(Term::Variable(_)) => return self.error(error::instantiation_error()),
+            Some(head) => match (term_parts(&head), parts.len()) {
+                (Some((_, args)), _) if !args.is_empty() => {
+                    return self.error(error::type_error("atomic", head))
+                }
+                (_, 0) => head,
+                (Some((name, _)), _) => make_term(name, parts.collect()),
+                (None, _) => return self.error(error::type_error("atom", head)),
+            },
+        };
+        self.unify(term, &built)
+    }
+
+    fn keysort(&mut self, pairs: &Term, sorted: &Term) -> bool {
+        let pairs = match self.proper_list(pairs) {
+            Ok(pairs) => pairs,
+            Err(formal) => return self.error(formal),
+        };
+        let mut keyed = vec![];
+        for pair in pairs {
+            match pair {
+                Term::Variable(_) => return self.error(error::instantiation_error()),
+                Term::CompoundTerm(name, args) if name == "-" && args.len() == 2 => {
+                    keyed.push((args[0].clone(), Term::CompoundTerm(name, args)))
+                }
+                pair => return self.error(error::type_error("pair", pair)),
+            }
+        }
+        keyed.sort_by(|(left, _), (right, _)| compare_terms(left, right));
+        let values = keyed.into_iter().map(|(_, pair)| pair).collect();
+        self.unify(sorted, &Term::List(values))
+    }
+
+    fn or_error(&mut self, result: Result<bool, Term>) -> bool {
+        result.unwrap_or_else(|formal| self.error(formal))
+    }
+
+    fn text(&self, value: &Term) -> Result<Option<String>, Term> {
+        match self.deref(value) {
+            Term::Variable(_) => Ok(None),
+            Term::Str(text) => Ok(Some(text)),
+            Term::Int(val) => Ok(Some(val.to_string())),
+            value => match term_parts(&value) {
+                Some((name, args)) if args.is_empty() => Ok(Some(name)),
+                _ => Err(error::type_error("atomic", self.resolve(&value))),
+            },
+        }
+    }
+
+    fn bound_text(&self, value: &Term) -> Result<String, Term> {
+        self.text(value)?.ok_or_else(error::instantiation_error)
+    }
+
+    fn fixed_int(&self, value: &Term) -> Result<Option<usize>, Term> {
+        match self.deref(value) {
+            Term::Int(val) => Ok(Some(val)),
+            Term::Variable(_) => Ok(None),
+            culprit => Err(error::type_error("integer", culprit)),
+        }
+    }
+
+    fn atom_length(&mut self, atom: &Term, length: &Term) -> Result<bool, Term> {
+        let text = self.bound_text(atom)?;
+        self.fixed_int(length)?;
+        Ok(self.unify(length, &Term::Int(text.chars().count())))
+    }
+
+    fn concat(
+        &mut self,
+        left: &Term,
+        right: &Term,
+        whole: &Term,
+        make: fn(String) -> Term,
+    ) -> Result<bool, Term> {
+        if let (Some(left), Some(right)) = (self.text(left)?, self.text(right)?) {
+            return Ok(self.unify(whole, &make(left + &right)));
+        }
+        let text = self.bound_text(whole)?;
+        let pair = |left, right| Term::CompoundTerm("-".to_string(), vec![left, right]);
+        let values = text
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain([text.len()])
+            .map(|idx| pair(make(text[..idx].to_string()), make(text[idx..].to_string())))
+            .collect();
+        Ok(self.call(Alternatives::Members {
+            goal: pair(left.clone(), right.clone()),
+            values,
+            next: 0,
+        }))
+    }
+
+    fn sub_atom(
+        &mut self,
+        atom: &Term,
+        before: &Term,
+        length: &Term,
+        after: &Term,
+        sub: &Term,
+    ) -> Result<bool, Term> {
+        let chars: Vec<char> = self.bound_text(atom)?.chars().collect();
+        let fixed_before = self.fixed_int(before)?;
+        let fixed_after = self.fixed_int(after)?;
+        let sub_text = self.text(sub)?;
+        let fixed_length = match &sub_text {
+            Some(text) => Some(text.chars().count()),
+            None => self.fixed_int(length)?,
+        };
+
+        let mut values = vec![];
+        for start in 0..=chars.len() {
+            if fixed_before.is_some_and(|before| before != start) {
+                continue;
+            }
+            for len in 0..=chars.len() - start {
+                let rest = chars.len() - start - len;
+                if fixed_length.is_some_and(|length| length != len)
+                    || fixed_after.is_some_and(|after| after != rest)
+                {
+                    continue;
+                }
+                let text: String = chars[start..start + len].iter().collect();
+                if sub_text.as_ref().is_some_and(|sub| *sub != text) {
+                    continue;
+                }
+                values.push(Term::CompoundTerm(
+                    "sub_atom".to_string(),
+                    vec![
+                        Term::Int(start),
+                        Term::Int(len),
+                        Term::Int(rest),
+                        atom_value(text),
+                    ],
+                ));
+            }
+        }
+        let goal = Term::CompoundTerm(
+            "sub_atom".to_string(),
+            vec![before.clone(), length.clone(), after.clone(), sub.clone()],
+        );
+        Ok(self.call(Alternatives::Members {
+            goal,
+            values,
+            next: 0,
+        }))
+    }
+
+    fn split_string(
+        &mut self,
+        string: &Term,
+        separators: &Term,
+        pad: &Term,
+        parts: &Term,
+    ) -> Result<bool, Term> {
+        let text = self.bound_text(string)?;
+        let separators: Vec<char> = self.bound_text(separators)?.chars().collect();
+        let pad: Vec<char> = self.bound_text(pad)?.chars().collect();
+        let fields: Vec<&str> = if separators.is_empty() {
+            vec![&text]
+        } else {
+            text.split(|c| separators.contains(&c)).collect()
+        };
+        let values = fields
+            .into_iter()
+            .map(|field| Term::Str(field.trim_matches(|c| pad.contains(&c)).to_string()))
+            .collect();
+        Ok(self.unify(parts, &Term::List(values)))
+    }
+
+    fn atom_string(&mut self, atom: &Term, string: &Term) -> Result<bool, Term> {
+        match self.text(atom)? {
+            Some(text) => Ok(self.unify(string, &Term::Str(text))),
+            None => {
+                let text = self.bound_text(string)?;
+                Ok(self.unify(atom, &atom_value(text)))
+            }
+        }
+    }
+
+    fn number_codes(&mut self, number: &Term, codes: &Term) -> Result<bool, Term> {
+        match self.deref(number) {
+            Term::Int(val) => {
+                let list = val
+                    .to_string()
+                    .chars()
+                    .map(|c| Term::Int(c as usize))
+                    .collect();
+                return Ok(self.unify(codes, &Term::List(list)));
+            }
+            Term::Variable(_) => (),
+            culprit => return Err(error::type_error("integer", culprit)),
+        }
+        let mut text = String::new();
+        for code in self.proper_list(codes)? {
+            match code {
+                Term::Int(code) => match u32::try_from(code).ok().and_then(char::from_u32) {
+                    Some(c) => text.push(c),
+                    None => return Err(error::representation_error("character_code")),
+                },
+                Term::Variable(_) => return Err(error::instantiation_error()),
+                culprit => return Err(error::type_error("integer", culprit)),
+            }
+        }
+        match text.trim().parse() {
+            Ok(val) => Ok(self.unify(number, &Term::Int(val))),
+            Err(_) => Err(error::syntax_error("illegal_number")),
+        }
+    }
+
+    fn atom_chars(&mut self, atom: &Term, chars: &Term) -> Result<bool, Term> {
+        if let Some(text) = self.text(atom)? {
+            let list = text.chars().map(|c| atom_value(c.to_string())).collect();
+            return Ok(self.unify(chars, &Term::List(list)));
+        }
+        let mut text = String::new();
+        for char in self.proper_list(chars)? {
+            match (&char, term_parts(&char)) {
+                (Term::Variable(_), _) => return Err(error::instantiation_error()),
+                (_, Some((name, args))) if args.is_empty() && name.chars().count() == 1 => {
+                    text.push_str(&name)
+                }
+                _ => return Err(error::type_error("character", char)),
+            }
+        }
+        Ok(self.unify(atom, &atom_value(text)))
+    }
+
+    fn string_code(&mut self, index: &Term, string: &Term, code: &Term) -> Result<bool, Term> {
+        let index = self
+            .fixed_int(index)?
+            .ok_or_else(error::instantiation_error)?;
+        let text = self.bound_text(string)?;
+        match index.checked_sub(1).and_then(|idx| text.chars().nth(idx)) {
+            Some(c) => Ok(self.unify(code, &Term::Int(c as usize))),
+            None => Ok(false),
+        }
+    }
+
+    fn length(&mut self, list: &Term, length: &Term) -> bool {
+        let Some((values, tail)) = self.list_parts(list) else {
+            return false;
+        };
+        match (tail, self.deref(length)) {
+            (None, length) => self.unify(&length, &Term::Int(values.len())),
+            (Some(tail), Term::Int(length)) => {
+                if length < values.len() {
+                    return false;
+                }
+                let suffix = self.fresh_suffix();
+                let fresh = (values.len()..length)
+                    .map(|idx| Term::Variable(format!("_{}#{}", idx, suffix)))
+                    .collect();
+                self.unify(&tail, &Term::List(fresh))
+            }
+            (Some(tail), length @ Term::Variable(_)) => self.call(Alternatives::Lengths {
+                prefix: values.len(),
+                tail,
+                length,
+                next: 0,
+            }),
+            (_, culprit) => self.error(error::type_error("integer", culprit)),
+        }
+    }
+
+    fn nth(&mut self, index: &Term, list: &Term, elem: &Term, base: usize) -> bool {
+        let Some((values, _)) = self.list_parts(list) else {
+            return false;
+        };
+        match self.deref(index) {
+            Term::Int(index) => match index.checked_sub(base).and_then(|idx| values.get(idx)) {
+                Some(value) => self.unify(elem, value),
+                None => false,
+            },
+            index @ Term::Variable(_) => {
+                let values = values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, value)| {
+                        Term::CompoundTerm("-".to_string(), vec![Term::Int(idx + base), value])
+                    })
+                    .collect();
+                self.call(Alternatives::Members {
+                    goal: Term::CompoundTerm("-".to_string(), vec![index, elem.clone()]),
+                    values,
+                    next: 0,
+                })
+            }
+            culprit => self.error(error::type_error("integer", culprit)),
+        }
+    }
+
+    fn sort(&mut self, key: &Term, order: &Term, list: &Term, sorted: &Term) -> bool {
+        let key = match self.deref(key) {
+            Term::Int(key) => key,
+            Term::Variable(_) => return self.error(error::instantiation_error()),
+            culprit => return self.error(error::type_error("integer", culprit)),
+        };
+        let (descending, dedup) = match self.deref(order) {
+            Term::CompoundTerm(name, args) if args.is_empty() => match name.as_str() {
+                "@<" => (false, true),
+                "@=<" => (false, false),
+                "@>" => (true, true),
+                "@>=" => (true, false),
+                _ => {
+                    let culprit = Term::CompoundTerm(name, args);
+                    return self.error(error::domain_error("order", culprit));
+                }
+            },
+            Term::Variable(_) => return self.error(error::instantiation_error()),
+            culprit => return self.error(error::domain_error("order", culprit)),
+        };
+        let values = match self.proper_list(list) {
+            Ok(values) => values,
+            Err(formal) => return self.error(formal),
+        };
+
+        let mut keyed = vec![];
+        for value in values {
+            let sort_key = if key == 0 {
+                value.clone()
+            } else {
+                match term_parts(&value) {
+                    Some((_, mut args)) if key <= args.len() => args.swap_remove(key - 1),
+                    _ => return self.error(error::type_error("compound", value)),
+                }
+            };
+            keyed.push((sort_key, value));
+        }
+        keyed.sort_by(|(left, _), (right, _)| {
+            let order = compare_terms(left, right);
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        if dedup {
+            keyed.dedup_by(|(right, _), (left, _)| compare_terms(left, right).is_eq());
+        }
+        let values = keyed.into_iter().map(|(_, value)| value).collect();
+        self.unify(sorted, &Term::List(values))
+    }
+
+    fn succeeds(&mut self, goal: &Term) -> Result<bool, Term> {
+        let goal = self.resolve(goal);
+        let mut solver = Solver::new(
+            self.db,
+            self.store,
+            self.tables.clone(),
+            HashMap::new(),
+            goal,
+        );
+        solver.renames = self.renames;
+        let solved = solver.next_solution();
+        self.renames = solver.renames;
+        solved
+    }
+
+    fn solutions(&mut self, template: &Term, goal: &Term) -> Result<Vec<Term>, Term> {
+        let template = self.resolve(template);
+        let goal = self.resolve(goal);
+        let mut solver = Solver::new(
+            self.db,
+            self.store,
+            self.tables.clone(),
+            HashMap::new(),
+            goal,
+        );
+        solver.renames = self.renames;
+        let mut results = vec![];
+        let solved = loop {
+            match solver.next_solution() {
+                Ok(true) => {
+                    let suffix = solver.fresh_suffix();
+                    results.push(rename(&solver.resolve(&template), &suffix));
+                }
+                Ok(false) => break Ok(results),
+                Err(ball) => break Err(ball),
+            }
+        };
+        self.renames = solver.renames;
+        solved
+    }
+
+    fn bagof(&mut self, template: &Term, goal: &Term, result: &Term, set: bool) -> bool {
+        let template = self.resolve(template);
+        let goal = self.resolve(goal);
+        let mut bound = vec![];
+        template.collect_vars(&mut bound);
+        let mut inner = &goal;
+        while let Term::CompoundTerm(name, args) = inner {
+            if name != "^" || args.len() != 2 {
+                break;
+            }
+            args[0].collect_vars(&mut bound);
+            inner = &args[1];
+        }
+        let mut free = vec![];
+        inner.collect_vars(&mut free);
+        free.retain(|name| !bound.contains(name));
+
+        let witness = Term::List(free.into_iter().map(Term::Variable).collect());
+        let pair = Term::CompoundTerm("-".to_string(), vec![witness.clone(), template]);
+        let solutions = match self.solutions(&pair, inner) {
+            Ok(solutions) => solutions,
+            Err(ball) => return self.throw(ball),
+        };
+
+        let mut groups: Vec<(Term, Vec<Term>)> = vec![];
+        for solution in solutions {
+            let Term::CompoundTerm(_, mut parts) = solution else {
+                unreachable!()
+            };
+            let instance = parts.pop().unwrap();
+            let witness = parts.pop().unwrap();
+            let key = variant(&witness);
+            match groups.iter_mut().find(|(other, _)| variant(other) == key) {
+                Some((_, instances)) => instances.push(instance),
+                None => groups.push((witness, vec![instance])),
+            }
+        }
+        if set {
+            groups.sort_by(|(left, _), (right, _)| compare_terms(left, right));
+            for (_, instances) in groups.iter_mut() {
+                sort_terms(instances);
+            }
+        }
+
+        let answers = groups
+            .into_iter()
+            .map(|(witness, instances)| {
+                Term::CompoundTerm("-".to_string(), vec![witness, Term::List(instances)])
+            })
+            .collect();
+        self.call(Alternatives::Answers {
+            goal: Term::CompoundTerm("-".to_string(), vec![witness, result.clone()]),
+            answers,
+            next: 0,
+        })
+    }
+
+    fn aggregate_all(&mut self, spec: &Term, goal: &Term, result: &Term) -> bool {
+        let (kind, template) = match self.resolve(spec) {
+            Term::Variable(_) => return self.error(error::instantiation_error()),
+            Term::CompoundTerm(name, args) if name == "count" && args.is_empty() => {
+                (name, error::atom("true"))
+            }
+            Term::CompoundTerm(name, mut args)
+                if ["sum", "max", "bag", "set"].contains(&name.as_str()) && args.len() == 1 =>
+            {
+                (name, args.pop().unwrap())
+            }
+            spec => return self.error(error::domain_error("aggregate_spec", spec)),
+        };
+        let mut solutions = match self.solutions(&template, goal) {
+            Ok(solutions) => solutions,
+            Err(ball) => return self.throw(ball),
+        };
+
+        let aggregate = match kind.as_str() {
+            "count" => Term::Int(solutions.len()),
+            "bag" => Term::List(solutions),
+            "set" => {
+                sort_terms(&mut solutions);
+                Term::List(solutions)
+            }
+            _ => {
+                let mut values = vec![];
+                for solution in solutions {
+                    match solution {
+                        Term::Int(val) => values.push(val),
+                        Term::Variable(_) => return self.error(error::instantiation_error()),
+                        culprit => return self.error(error::type_error("integer", culprit)),
                     }
                 }
+                match (kind.as_str(), values.iter().max()) {
+                    ("sum", _) => Term::Int(values.iter().sum()),
+                    (_, Some(max)) => Term::Int(*max),
+                    (_, None) => return false,
+                }
+            }
+        };
+        self.unify(result, &aggregate)
+    }
+
+    fn call_tabled(&mut self, goal: &Term) -> bool {
+        let key = variant(&self.resolve(goal));
+
+        let (exists, complete, evaluating) = match self.tables.borrow().tables.get(&key) {
+            Some(table) => (true, table.complete, table.evaluating),
+            None => (false, false, false),
+        };
+        if evaluating {
+            let mut tables = self.tables.borrow_mut();
+            let position = tables.created.iter().position(|k| *k == key).unwrap();
+            if let Some(lowest) = tables.stack.last_mut() {
+                *lowest = (*lowest).min(position);
+            }
+        } else if !exists || !complete {
+            if let Err(ball) = self.evaluate_table(&key) {
+                return self.throw(ball);
+            }
+        }
+
+        let answers = self.tables.borrow().tables[&key].answers.clone();
+        self.call(Alternatives::Answers {
+            goal: goal.clone(),
+            answers,
+            next: 0,
+        })
+    }
+
+    fn evaluate_table(&mut self, key: &Term) -> Result<(), Term> {
+        let position = {
+            let mut tables = self.tables.borrow_mut();
+            if !tables.tables.contains_key(key) {
+                tables.tables.insert(key.clone(), Table::default());
+                tables.created.push(key.clone());
+            }
+            let position = tables.created.iter().position(|k| k == key).unwrap();
+            tables.tables.get_mut(key).unwrap().evaluating = true;
+            tables.stack.push(position);
+            position
+        };
+
+        let mut result = Ok(());
+        while result.is_ok() {
+            let before = self.tables.borrow().answers_added;
+            let goal = rename(key, &self.fresh_suffix());
+            let mut solver =
+                Solver::for_clauses(self.db, self.store, self.tables.clone(), goal.clone());
+            loop {
+                match solver.next_solution() {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(ball) => {
+                        result = Err(ball);
+                        break;
+                    }
+                }
+                let answer = variant(&solver.resolve(&goal));
+                let mut tables = self.tables.borrow_mut();
+                let table = tables.tables.get_mut(key).unwrap();
+                if table.seen.insert(answer.clone()) {
+                    table.answers.push(answer);
+                    tables.answers_added += 1;
+                }
+            }
+            if self.tables.borrow().answers_added == before {
+                break;
+            }
+        }
+
+        let Tables {
+            tables,
+            created,
+            stack,
+            ..
+        } = &mut *self.tables.borrow_mut();
+        let lowest = stack.pop().unwrap();
+        tables.get_mut(key).unwrap().evaluating = false;
+        result?;
+        if lowest >= position {
+            for created in created[position..].iter() {
+                tables.get_mut(created).unwrap().complete = true;
+            }
+        } else if let Some(parent) = stack.last_mut() {
+            *parent = (*parent).min(lowest);
+        }
+        Ok(())
+    }
+
+    fn compare(&mut self, op: &str, left: &Term, right: &Term) -> bool {
+        let compare = |left: usize, right: usize| match op {
+            ">" => left > right,
+            "<" => left < right,
+            ">=" => left >= right,
+            "<=" => left <= right,
+            _ => unreachable!(),
+        };
+
+        match (self.deref(left), self.deref(right)) {
+            (Term::Int(left), Term::Int(right)) => compare(left, right),
+            (Term::Variable(_), Term::Variable(_)) => self.error(error::instantiation_error()),
+            (Term::Variable(name), Term::Int(right)) => {
+                // TODO: use random number generator
+                let witness = match op {
+                    ">" => right.checked_add(1),
+                    "<" => right.checked_sub(1),
+                    _ => Some(right),
+                };
+                self.bind_witness(&name, witness)
+            }
+            (Term::Int(left), Term::Variable(name)) => {
+                // TODO: use random number generator
+                let witness = match op {
+                    ">" => left.checked_sub(1),
+                    "<" => left.checked_add(1),
+                    _ => Some(left),
+                };
+                self.bind_witness(&name, witness)
+            }
+            (Term::Int(_) | Term::Variable(_), culprit) | (culprit, _) => {
+                self.error(error::type_error("integer", culprit))
+            }
+        }
+    }
+
+    fn bind_witness(&mut self, name: &str, witness: Option<usize>) -> bool {
+        match witness {
+            Some(val) => {
+                self.bind(name, Term::Int(val));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn fresh_suffix(&mut self) -> String {
+        self.renames += 1;
+        self.renames.to_string()
+    }
+
+    fn rename_clause(&mut self, clause: &Clause) -> Clause {
+        let suffix = self.fresh_suffix();
+        Clause {
+            head: rename(&clause.head, &suffix),
+            body: rename(&clause.body, &suffix),
+        }
+    }
+
+    fn bind(&mut self, name: &str, value: Term) {
+        self.bindings.insert(name.to_owned(), value);
+        self.trail.push(name.to_owned());
+    }
+
+    fn undo(&mut self, trail_len: usize) {
+        for name in self.trail.drain(trail_len..) {
+            self.bindings.remove(&name);
+        }
+    }
+
+    fn deref(&self, value: &Term) -> Term {
+        let mut value = value;
+        while let Term::Variable(name) = value {
+            match self.bindings.get(name) {
+                Some(bound) => value = bound,
+                None => break,
+            }
+        }
+        value.clone()
+    }
+
+    fn resolve(&self, value: &Term) -> Term {
+        match self.deref(value) {
+            Term::Cons(head, tail) => match self.resolve(&tail) {
+                Term::List(mut values) => {
+                    values.insert(0, self.resolve(&head));
+                    Term::List(values)
+                }
+                tail => Term::Cons(Box::new(self.resolve(&head)), Box::new(tail)),
+            },
+            value => map_args(&value, &|arg| self.resolve(arg)),
+        }
+    }
+
+    fn unify(&mut self, left: &Term, right: &Term) -> bool {
+        match (self.deref(left), self.deref(right)) {
+            (Term::Variable(a), Term::Variable(b)) if a == b => true,
+            (Term::Variable(name), value) | (value, Term::Variable(name)) => {
+                self.bind(&name, value);
+                true
+            }
+            (Term::CompoundTerm(name_a, args_a), Term::CompoundTerm(name_b, args_b)) => {
+                name_a == name_b
+                    && args_a.len() == args_b.len()
+                    && args_a
+                        .iter()
+                        .zip(args_b.iter())
+                        .all(|(a, b)| self.unify(a, b))
+            }
+            (Term::List(a), Term::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| self.unify(a, b))
+            }
+            (Term::Cons(head, tail), Term::List(values))
+            | (Term::List(values), Term::Cons(head, tail)) => match values.split_first() {
+                Some((first, rest)) => {
+                    self.unify(&head, first) && self.unify(&tail, &Term::List(rest.to_vec()))
+                }
+                None => false,
+            },
+            (Term::Cons(head_a, tail_a), Term::Cons(head_b, tail_b)) => {
+                self.unify(&head_a, &head_b) && self.unify(&tail_a, &tail_b)
+            }
+            (Term::Str(a), Term::Str(b)) => a == b,
+            (Term::Int(a), Term::Int(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+fn compare_terms(left: &Term, right: &Term) -> Ordering {
+    let rank = |value: &Term| match value {
+        Term::Variable(_) => 0,
+        Term::Int(_) => 1,
+        Term::Str(_) => 3,
+        _ => match term_parts(value) {
+            Some((_, args)) if args.is_empty() => 2,
+            _ => 4,
+        },
+    };
+    match (left, right) {
+        (Term::Variable(left), Term::Variable(right)) => left.cmp(right),
+        (Term::Int(left), Term::Int(right)) => left.cmp(right),
+        (Term::Str(left), Term::Str(right)) => left.cmp(right),
+        (Term::List(left), Term::List(right)) => left
+            .iter()
+            .zip(right.iter())
+            .map(|(left, right)| compare_terms(left, right))
+            .find(|order| order.is_ne())
+            .unwrap_or_else(|| left.len().cmp(&right.len())),
+        _ => match (term_parts(left), term_parts(right)) {
+            (Some((left_name, left_args)), Some((right_name, right_args))) => left_args
+                .len()
+                .cmp(&right_args.len())
+                .then_with(|| left_name.cmp(&right_name))
+                .then_with(|| {
+                    left_args
+                        .iter()
+                        .zip(right_args.iter())
+                        .map(|(left, right)| compare_terms(left, right))
+                        .find(|order| order.is_ne())
+                        .unwrap_or(Ordering::Equal)
+                }),
+            _ => rank(left).cmp(&rank(right)),
+        },
+    }
+}
+
+fn sort_terms(values: &mut Vec<Term>) {
+    values.sort_by(compare_terms);
+    values.dedup();
+}
+
+fn term_parts(value: &Term) -> Option<(String, Vec<Term>)> {
+    let pair = |name: &str, left: &Term, right: &Term| {
+        Some((name.to_string(), vec![left.clone(), right.clone()]))
+    };
+    match value {
+        Term::CompoundTerm(name, args) => Some((name.clone(), args.clone())),
+        Term::List(values) => match values.split_first() {
+            Some((head, tail)) => pair(".", head, &Term::List(tail.to_vec())),
+            None => Some(("[]".to_string(), vec![])),
+        },
+        Term::Cons(head, tail) => pair(".", head, tail),
+        Term::Str(_) | Term::Int(_) | Term::Variable(_) => None,
+    }
+}
+
+fn atom_value(name: String) -> Term {
+    make_term(name, vec![])
+}
+
+fn make_term(name: String, mut args: Vec<Term>) -> Term {
+    match (name.as_str(), args.len()) {
+        ("[]", 0) => Term::List(vec![]),
+        (".", 2) => match args.pop().unwrap() {
+            Term::List(mut tail) => {
+                tail.insert(0, args.pop().unwrap());
+                Term::List(tail)
+            }
+            tail => Term::Cons(Box::new(args.pop().unwrap()), Box::new(tail)),
+        },
+        _ => Term::CompoundTerm(name, args),
+    }
+}
+
+fn rename(value: &Term, suffix: &str) -> Term {
+    substitute(value, &|name| {
+        Term::Variable(format!("{}#{}", name, suffix))
+    })
+}
+
+fn variant(value: &Term) -> Term {
+    let mut names = vec![];
+    value.collect_vars(&mut names);
+    substitute(value, &|name| {
+        let idx = names.iter().position(|n| n == name).unwrap();
+        Term::Variable(format!("#{}", idx))
+    })
+}
+
+fn substitute(value: &Term, var: &dyn Fn(&str) -> Term) -> Term {
+    match value {
+        Term::Variable(name) => var(name),
+        _ => map_args(value, &|arg| substitute(arg, var)),
+    }
+}
+
+fn map_args(value: &Term, f: &dyn Fn(&Term) -> Term) -> Term {
+    let map_box = |value: &Term| Box::new(f(value));
+    match value {
+        Term::CompoundTerm(name, args) => {
+            Term::CompoundTerm(name.clone(), args.iter().map(f).collect())
+        }
+        Term::List(values) => Term::List(values.iter().map(f).collect()),
+        Term::Cons(head, tail) => Term::Cons(map_box(head), map_box(tail)),
+        Term::Variable(_) | Term::Str(_) | Term::Int(_) => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser::Parser};
+
+    fn db(program: &str) -> DB {
+        let tokens = lexer::tokenize(program.to_string()).unwrap();
+        DB::new(Parser::new(tokens).parse().unwrap())
+    }
+
+    fn answers(db: &DB, query: &str, var: &str) -> Vec<String> {
+        let tokens = lexer::tokenize(query.to_string()).unwrap();
+        let query = Parser::new(tokens).parse_query().unwrap();
+        let mut answers: Vec<String> = db
+            .query_all(&query)
+            .unwrap()
+            .iter()
+            .map(|answer| answer[var].to_string())
+            .collect();
+        answers.sort();
+        answers
+    }
+
+    fn thrown(db: &DB, goal: &str) -> String {
+        let query = format!("catch(({goal}), error(?formal, ?context), true).");
+        let formal = answers(db, &query, "formal");
+        assert_eq!(formal.len(), 1, "{goal}");
+        formal.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn tabling_left_recursion() {
+        let db = db("
+            :- table path/2.
+            edge(a, b). edge(b, c). edge(c, a). edge(c, d).
+            path(?x, ?y) :- path(?x, ?z), edge(?z, ?y).
+            path(?x, ?y) :- edge(?x, ?y).
+        ");
+        assert_eq!(answers(&db, "path(a, ?y).", "y"), ["a", "b", "c", "d"]);
+        assert_eq!(answers(&db, "path(d, ?y).", "y"), Vec::<String>::new());
+        assert_eq!(answers(&db, "path(?x, ?y).", "x").len(), 12);
+    }
+
+    #[test]
+    fn tabling_mutual_recursion() {
+        let db = db("
+            :- table even/1.
+            :- table odd/1.
+            next(0, 1). next(1, 2). next(2, 3). next(3, 4). next(4, 5).
+            even(0).
+            even(?x) :- odd(?y), next(?y, ?x).
+            odd(?x) :- even(?y), next(?y, ?x).
+        ");
+        assert_eq!(answers(&db, "even(?x).", "x"), ["0", "2", "4"]);
+        assert_eq!(answers(&db, "odd(?x).", "x"), ["1", "3", "5"]);
+    }
+
+    #[test]
+    fn tabling_mutual_cycle() {
+        let db = db("
+            :- table a/1.
+            :- table b/1.
+            a(?x) :- b(?x).
+            b(?x) :- a(?x).
+            a(1).
+            b(2).
+        ");
+        assert_eq!(answers(&db, "a(?x).", "x"), ["1", "2"]);
+        assert_eq!(answers(&db, "b(?x).", "x"), ["1", "2"]);
+    }
+
+    #[test]
+    fn tabled_predicates_without_clauses() {
+        let db = db("
+            :- table missing/1.
+            :- table empty/1.
+            :- dynamic empty/1.
+            :- table path/2.
+            path(?x, ?y) :- edge(?x, ?y).
+        ");
+        assert!(matches!(
+            db.query_all(&term("missing(?x).")),
+            Err(Error::Existence(name, 1)) if name == "missing"
+        ));
+        assert!(matches!(
+            db.query_all(&term("path(a, ?y).")),
+            Err(Error::Existence(name, 2)) if name == "edge"
+        ));
+        assert_eq!(answers(&db, "empty(?x).", "x"), Vec::<String>::new());
+        assert_eq!(
+            answers(&db, "catch(missing(?x), error(?e, ?c), true).", "e"),
+            ["existence_error(procedure, missing / 1)"]
+        );
+
+        db.set_unknown(Unknown::Fail);
+        assert_eq!(answers(&db, "missing(?x).", "x"), Vec::<String>::new());
+        db.query_all(&term("assertz(missing(1)).")).unwrap();
+        assert_eq!(answers(&db, "missing(?x).", "x"), ["1"]);
+    }
+
+    #[test]
+    fn consult_runs_queries_in_source_order() {
+        let tokens = lexer::tokenize(
+            "
+            :- dynamic foo.
+            :- table foo.
+            :- set_prolog_flag(unknown, bogus).
+            p(1).
+            ?- p(?x).
+            p(2).
+            ?- assertz(p(3)).
+            ?- p(?x).
+        "
+            .to_string(),
+        )
+        .unwrap();
+        let mut seen = vec![];
+        let db = DB::consult(Parser::new(tokens).parse().unwrap(), |db, query| {
+            seen.push(db.query_all(query).unwrap().len());
+        });
+        assert_eq!(seen, [1, 1, 3]);
+        assert!(db.tabled.is_empty());
+        assert_eq!(db.unknown.get(), Unknown::Error);
+    }
+
+    fn term(source: &str) -> Term {
+        let tokens = lexer::tokenize(source.to_string()).unwrap();
+        Parser::new(tokens).parse_query().unwrap()
+    }
+
+    fn clause(source: &str) -> Clause {
+        match term(source) {
+            Term::CompoundTerm(name, mut args) if name == ":-" && args.len() == 2 => {
+                let body = args.pop().unwrap();
+                Clause {
+                    head: args.pop().unwrap(),
+                    body,
+                }
             }
+            head => Clause {
+                head,
+                body: error::atom("true"),
+            },
+        }
+    }
+
+    #[test]
+    fn transaction_commits() {
+        let db = db("item(1).");
+        let result: Result<usize, Error> = db.transaction(|tx| {
+            tx.assertz(clause("item(2)."))?;
+            db.assertz(clause("item(3)."))?;
+            assert!(tx.retract(&term("item(1)"))?);
+            Ok(tx.query_all(&term("item(?x)."))?.len())
+        });
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(answers(&db, "item(?x).", "x"), ["2", "3"]);
+    }
+
+    #[test]
+    fn transaction_rolls_back() {
+        let db = db("item(1).");
+        let result: Result<(), Error> = db.transaction(|tx| {
+            tx.assertz(clause("item(2)."))?;
+            Err(Error::Syntax("rollback".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(answers(&db, "item(?x).", "x"), ["1"]);
 
-            (_, Value::Predicate(..)) => false,
-            (Value::CompoundTerm(_, _), Value::Str(_)) => false,
-            (Value::CompoundTerm(_, _), Value::Int(_)) => false,
-            (Value::CompoundTerm(_, _), Value::List(_)) => false,
-            (Value::List(_), Value::CompoundTerm(_, _)) => false,
-            (Value::List(a), Value::List(b)) => {
-                a.len() == b.len()
-                    && a.iter()
-                        .zip(b)
-                        .all(|(query, fact)| self.unify(query, fact, map))
-            }
-            (Value::List(_), Value::Str(_)) => todo!(),
-            (Value::List(_), Value::Int(_)) => todo!(),
-            (Value::Str(_), Value::List(_)) => todo!(),
-            (Value::Int(_), Value::List(_)) => todo!(),
-            (Value::Eq(_, _), _) => todo!(),
-            (_query, Value::Eq(_left, _right)) => {
-                todo!()
-            }
-            (Value::And(_, _), _) => todo!(),
-            (query, Value::And(left, right)) => {
-                self.unify(query, left, map) && self.unify(query, right, map)
-            }
-            (Value::List(_), _) => todo!(),
-            (Value::Str(_), _) => todo!(),
-            (Value::Int(_), _) => todo!(),
-            (Value::GreaterThan(_, _), _) => todo!(),
-            (Value::LessThan(_, _), _) => todo!(),
-            (Value::GreaterThanEqual(_, _), _) => todo!(),
-            (Value::LessThanEqual(_, _), _) => todo!(),
-            (Value::CompoundTerm(_, _), _) => todo!(),
+        let result: Result<(), Error> = db.transaction(|tx| {
+            tx.query(&term("assertz(item(2)), missing."), &mut HashMap::new())?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(Error::Existence(name, 0)) if name == "missing"));
+        assert_eq!(answers(&db, "item(?x).", "x"), ["1"]);
+    }
+
+    #[test]
+    fn transaction_builtin() {
+        let db = db("
+            :- dynamic item/1.
+            add(?x) :- assertz(item(?x)).
+            add(?x) :- assertz(item(second)).
+            fails :- assertz(item(failed)), fail.
+            throws :- assertz(item(thrown)), throw(oops).
+        ");
+        assert_eq!(db.query_all(&term("add(first).")).unwrap().len(), 2);
+        assert!(db.retract_all(&term("item(?x)")).is_ok());
+        assert_eq!(
+            db.query_all(&term("transaction(add(first))."))
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(answers(&db, "item(?x).", "x"), ["first"]);
+        assert!(db
+            .query_all(&term("transaction(fails)."))
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            db.query_all(&term("transaction(throws).")),
+            Err(Error::Uncaught(_))
+        ));
+        assert_eq!(answers(&db, "item(?x).", "x"), ["first"]);
+    }
+
+    #[test]
+    fn reopen_keeps_persistent_clauses() {
+        let dir = std::env::temp_dir().join(format!("reopen-{}", std::process::id()));
+        let program = || {
+            let tokens = lexer::tokenize(
+                "
+                :- persistent saved/1.
+                :- dynamic saved/1.
+                :- dynamic scratch/1.
+                saved(0).
+            "
+                .to_string(),
+            )
+            .unwrap();
+            Parser::new(tokens).parse().unwrap()
+        };
+
+        let db = DB::open(program(), &dir).unwrap();
+        db.assertz(clause("saved(1).")).unwrap();
+        db.assertz(clause("scratch(1).")).unwrap();
+        db.query_all(&term(
+            "assertz(saved(2)), assertz(scratch(2)), retract(saved(0)).",
+        ))
+        .unwrap();
+        db.transaction(|tx| {
+            tx.assertz(clause("saved(3)."))?;
+            tx.assertz(clause("scratch(3)."))?;
+            Ok::<(), Error>(())
+        })
+        .unwrap();
+        assert_eq!(answers(&db, "scratch(?x).", "x"), ["1", "2", "3"]);
+        drop(db);
+
+        let db = DB::open(program(), &dir).unwrap();
+        let saved = answers(&db, "saved(?x).", "x");
+        let scratch = answers(&db, "scratch(?x).", "x");
+        drop(db);
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(saved, ["1", "2", "3"]);
+        assert_eq!(scratch, Vec::<String>::new());
+    }
+
+    #[test]
+    fn library_helpers_do_not_collide() {
+        let db = db("
+            reverse_acc(?x, ?y, mine).
+            append(mine, ?l, ?l).
+        ");
+        assert_eq!(answers(&db, "reverse([1, 2, 3], ?r).", "r"), ["[3, 2, 1]"]);
+        assert_eq!(answers(&db, "reverse_acc(a, b, ?r).", "r"), ["mine"]);
+        assert_eq!(answers(&db, "append(?x, [1], [1]).", "x"), ["mine"]);
+        assert!(matches!(
+            self::db("").query_all(&term("reverse_acc([1], [], ?r).")),
+            Err(Error::Existence(name, 3)) if name == "reverse_acc"
+        ));
+    }
+
+    #[test]
+    fn snapshot_save_and_load() {
+        let db = db("
+            :- table path/2.
+            :- set_prolog_flag(unknown, fail).
+            edge(a, b). edge(b, c). edge(c, a).
+            path(?x, ?y) :- path(?x, ?z), edge(?z, ?y).
+            path(?x, ?y) :- edge(?x, ?y).
+            name(1, \"one\"). name(2, [t, w, o]). name(3, [?h|?t]).
+        ");
+        let path = std::env::temp_dir().join(format!("snapshot-{}.db", std::process::id()));
+        db.save(&path).unwrap();
+        let loaded = DB::load(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        for (query, var) in [
+            ("path(a, ?y).", "y"),
+            ("name(?n, ?x).", "x"),
+            ("name(2, [?c|?r]).", "r"),
+        ] {
+            assert_eq!(answers(&loaded, query, var), answers(&db, query, var));
         }
+        assert_eq!(answers(&loaded, "path(a, ?y).", "y"), ["a", "b", "c"]);
+        assert_eq!(answers(&loaded, "missing(?x).", "x"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn snapshot_is_deterministic() {
+        let program = "
+            :- table a/1. :- table b/1. :- table c/1.
+            item(a). item(b). item(1). item(\"s\"). item(f(x)). item([1]). item(?x).
+            other(z). other(y). other(x).
+        ";
+        let first = std::env::temp_dir().join(format!("first-{}.db", std::process::id()));
+        let second = std::env::temp_dir().join(format!("second-{}.db", std::process::id()));
+        db(program).save(&first).unwrap();
+        db(program).save(&second).unwrap();
+        let (first_bytes, second_bytes) = (fs::read(&first).unwrap(), fs::read(&second).unwrap());
+        fs::remove_file(&first).ok();
+        fs::remove_file(&second).ok();
+        assert_eq!(first_bytes, second_bytes);
+    }
+
+    #[test]
+    fn snapshot_rejects_bad_index_positions() {
+        let tokens = lexer::tokenize("item(1).".to_string()).unwrap();
+        let Item::Clause(clause) = Parser::new(tokens).parse().unwrap().remove(0) else {
+            unreachable!()
+        };
+        let path = std::env::temp_dir().join(format!("index-{}.db", std::process::id()));
+        for (variable, keyed) in [(vec![], vec![1]), (vec![0, 0], vec![0]), (vec![], vec![0])] {
+            let ok = variable.is_empty();
+            let mut writer = snapshot::Writer::new();
+            writer.atom("error");
+            writer.u32(0);
+            writer.u32(0);
+            writer.u32(1);
+            writer.atom("item");
+            writer.u32(1);
+            writer.u32(1);
+            writer.value(&clause.to_term());
+            writer.u32(variable.len() as u32);
+            for position in variable {
+                writer.u32(position);
+            }
+            writer.u32(1);
+            writer.value(&Term::Int(1));
+            writer.u32(keyed.len() as u32);
+            for position in keyed.iter() {
+                writer.u32(*position);
+            }
+            fs::write(&path, writer.finish()).unwrap();
+            let loaded = DB::load(&path);
+            if keyed == [0] && ok {
+                assert_eq!(answers(&loaded.unwrap(), "item(?x).", "x"), ["1"]);
+            } else {
+                assert_eq!(loaded.err().unwrap().kind(), io::ErrorKind::InvalidData);
+            }
+        }
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn snapshot_rejects_corrupt_files() {
+        let path = std::env::temp_dir().join(format!("corrupt-{}.db", std::process::id()));
+        db("item(1).").save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            DB::load(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        bytes[10] ^= 0xff;
+        bytes[4] = 1;
+        let len = bytes.len() - 4;
+        let checksum = crate::journal::crc32(&bytes[..len]);
+        bytes[len..].copy_from_slice(&checksum.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let err = DB::load(&path).unwrap_err();
+        fs::remove_file(&path).ok();
+        assert_eq!(err.to_string(), "unsupported snapshot version 1");
+    }
+
+    #[test]
+    fn logical_update_view() {
+        let db = db("
+            :- dynamic q/1.
+            p(1). p(2).
+        ");
+        assert_eq!(answers(&db, "p(?x), assertz(p(3)).", "x"), ["1", "2"]);
+        assert_eq!(answers(&db, "p(?x).", "x"), ["1", "2", "3", "3"]);
+        assert_eq!(answers(&db, "p(?x), retract(p(3)).", "x"), ["1", "1"]);
+        assert_eq!(answers(&db, "p(?x).", "x"), ["1", "2"]);
+        assert_eq!(answers(&db, "asserta(p(0)), p(?x).", "x"), ["0", "1", "2"]);
+        assert_eq!(answers(&db, "retract(p(?x)).", "x"), ["0", "1", "2"]);
+        assert_eq!(answers(&db, "p(?x).", "x"), Vec::<String>::new());
+
+        db.assertz(clause("q(a).")).unwrap();
+        db.assertz(clause("q(b).")).unwrap();
+        assert_eq!(answers(&db, "q(?x), retractall(q(?y)).", "x"), ["a", "b"]);
+        assert_eq!(answers(&db, "q(?x).", "x"), Vec::<String>::new());
+
+        assert_eq!(thrown(&db, "assertz(?x)"), "instantiation_error");
+        assert_eq!(thrown(&db, "assertz(1)"), "type_error(callable, 1)");
+        assert_eq!(
+            thrown(&db, "asserta((foo :- 1))"),
+            "type_error(callable, 1)"
+        );
+        assert_eq!(thrown(&db, "retract(?x)"), "instantiation_error");
+        assert_eq!(
+            db.query_all(&term("assertz((foo :- 1)).")),
+            Err(Error::Type("callable".to_string(), Term::Int(1)))
+        );
+    }
+
+    #[test]
+    fn throw_and_catch() {
+        let db = db("
+            p(1). p(2).
+            q(?x) :- ?x > a.
+        ");
+        assert_eq!(answers(&db, "catch(throw(f(1)), f(?x), true).", "x"), ["1"]);
+        assert_eq!(
+            answers(&db, "catch((?y = 1, throw(e)), e, true).", "y"),
+            ["?y"]
+        );
+        assert_eq!(
+            answers(&db, "catch(catch(throw(a), b, true), ?e, true).", "e"),
+            ["a"]
+        );
+        assert_eq!(answers(&db, "catch(p(?x), ?e, true).", "x"), ["1", "2"]);
+        assert_eq!(
+            answers(&db, "catch(throw(e), e, fail).", "x"),
+            Vec::<String>::new()
+        );
+        assert_eq!(thrown(&db, "throw(?ball)"), "instantiation_error");
+        assert_eq!(thrown(&db, "q(1)"), "type_error(integer, a)");
+        assert_eq!(
+            thrown(&db, "missing"),
+            "existence_error(procedure, missing / 0)"
+        );
+
+        let error = |query: &str| db.query_all(&term(query)).unwrap_err();
+        assert_eq!(
+            error("catch(throw(foo), bar, true)."),
+            Error::Uncaught(term("foo."))
+        );
+        assert_eq!(
+            error("catch(throw(e), e, throw(again))."),
+            Error::Uncaught(term("again."))
+        );
+        assert_eq!(
+            error("throw(error(foo, c))."),
+            Error::Uncaught(term("error(foo, c)."))
+        );
+        assert_eq!(error("throw(?ball)."), Error::Instantiation);
+        assert_eq!(
+            error("q(1)."),
+            Error::Type("integer".to_string(), term("a."))
+        );
+        assert_eq!(
+            error("missing(1)."),
+            Error::Existence("missing".to_string(), 1)
+        );
+        assert_eq!(
+            error("throw(error(domain_error(order, x), c))."),
+            Error::Domain("order".to_string(), term("x."))
+        );
+        assert_eq!(
+            error("throw(error(representation_error(max_arity), c))."),
+            Error::Representation("max_arity".to_string())
+        );
+        assert_eq!(
+            error("throw(error(syntax_error(illegal_number), c))."),
+            Error::Syntax("illegal_number".to_string())
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let db = db("p.");
+        assert_eq!(answers(&db, "?x > 3.", "x"), ["4"]);
+        assert_eq!(answers(&db, "1 > ?x.", "x"), ["0"]);
+        assert_eq!(answers(&db, "?x < 0.", "x"), Vec::<String>::new());
+        assert_eq!(answers(&db, "?y = 2, ?y >= 2, 1 <= ?y.", "y"), ["2"]);
+        assert_eq!(thrown(&db, "?x > ?y"), "instantiation_error");
+        assert_eq!(thrown(&db, "1 > a"), "type_error(integer, a)");
+        assert_eq!(thrown(&db, "\"s\" < 1"), "type_error(integer, \"s\")");
+        assert_eq!(thrown(&db, "f(1) >= 1"), "type_error(integer, f(1))");
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let db = db("
+            :- dynamic declared/1.
+            known(1).
+        ");
+        assert_eq!(answers(&db, "current_prolog_flag(?f, ?v).", "v"), ["error"]);
+        assert_eq!(
+            thrown(&db, "missing(?x)"),
+            "existence_error(procedure, missing / 1)"
+        );
+        assert_eq!(
+            thrown(&db, "call(known, 1, 2)"),
+            "existence_error(procedure, known / 2)"
+        );
+        assert_eq!(answers(&db, "declared(?x).", "x"), Vec::<String>::new());
+
+        assert_eq!(
+            thrown(&db, "set_prolog_flag(?f, fail)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "set_prolog_flag(unknown, ?v)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "set_prolog_flag(1, fail)"),
+            "type_error(atom, 1)"
+        );
+        assert_eq!(
+            thrown(&db, "set_prolog_flag(bogus, fail)"),
+            "domain_error(prolog_flag, bogus)"
+        );
+        assert_eq!(
+            thrown(&db, "set_prolog_flag(unknown, bogus)"),
+            "domain_error(flag_value, unknown + bogus)"
+        );
+        assert_eq!(
+            thrown(&db, "set_prolog_flag(unknown, fail(x))"),
+            "domain_error(flag_value, unknown + fail(x))"
+        );
+        assert_eq!(db.unknown.get(), Unknown::Error);
+
+        db.query_all(&term("set_prolog_flag(unknown, fail)."))
+            .unwrap();
+        assert_eq!(
+            answers(&db, "current_prolog_flag(unknown, ?v).", "v"),
+            ["fail"]
+        );
+        assert_eq!(answers(&db, "missing(?x).", "x"), Vec::<String>::new());
+        db.set_unknown(Unknown::Warning);
+        assert_eq!(
+            answers(&db, "current_prolog_flag(unknown, ?v).", "v"),
+            ["warning"]
+        );
+        assert_eq!(answers(&db, "missing(?x).", "x"), Vec::<String>::new());
+        db.set_unknown(Unknown::Error);
+        assert_eq!(
+            db.query_all(&term("missing(?x).")),
+            Err(Error::Existence("missing".to_string(), 1))
+        );
+    }
+
+    #[test]
+    fn all_solutions() {
+        let db = db("q(1, a). q(2, b). q(3, a).");
+        assert_eq!(
+            answers(&db, "findall(?x, q(?x, ?y), ?l).", "l"),
+            ["[1, 2, 3]"]
+        );
+        assert_eq!(answers(&db, "findall(?x, q(?x, c), ?l).", "l"), ["[]"]);
+        assert_eq!(
+            answers(&db, "findall(?x, q(?x, ?y), [1 | ?t]).", "t"),
+            ["[2, 3]"]
+        );
+        assert_eq!(
+            answers(&db, "bagof(?x, q(?x, ?y), ?l).", "l"),
+            ["[1, 3]", "[2]"]
+        );
+        assert_eq!(answers(&db, "bagof(?x, q(?x, ?y), ?l).", "y"), ["a", "b"]);
+        assert_eq!(
+            answers(&db, "bagof(?x, ?y ^ q(?x, ?y), ?l).", "l"),
+            ["[1, 2, 3]"]
+        );
+        assert_eq!(
+            answers(&db, "bagof(?x, q(?x, c), ?l).", "l"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            answers(&db, "setof(?y, ?x ^ q(?x, ?y), ?l).", "l"),
+            ["[a, b]"]
+        );
+        assert_eq!(
+            answers(&db, "setof(?x, q(?x, c), ?l).", "l"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            answers(&db, "aggregate_all(count, q(?x, ?y), ?n).", "n"),
+            ["3"]
+        );
+        assert_eq!(
+            answers(&db, "aggregate_all(sum(?x), q(?x, ?y), ?n).", "n"),
+            ["6"]
+        );
+        assert_eq!(
+            answers(&db, "aggregate_all(max(?x), q(?x, ?y), ?n).", "n"),
+            ["3"]
+        );
+        assert_eq!(
+            answers(&db, "aggregate_all(bag(?y), q(?x, ?y), ?n).", "n"),
+            ["[a, b, a]"]
+        );
+        assert_eq!(
+            answers(&db, "aggregate_all(set(?y), q(?x, ?y), ?n).", "n"),
+            ["[a, b]"]
+        );
+        assert_eq!(answers(&db, "aggregate_all(count, fail, ?n).", "n"), ["0"]);
+        assert_eq!(
+            answers(&db, "aggregate_all(sum(?x), fail, ?n).", "n"),
+            ["0"]
+        );
+        assert_eq!(
+            answers(&db, "aggregate_all(max(?x), fail, ?n).", "n"),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(thrown(&db, "findall(?x, ?g, ?l)"), "instantiation_error");
+        assert_eq!(thrown(&db, "findall(?x, 1, ?l)"), "type_error(callable, 1)");
+        assert_eq!(thrown(&db, "bagof(?x, ?g, ?l)"), "instantiation_error");
+        assert_eq!(thrown(&db, "setof(?x, 1, ?l)"), "type_error(callable, 1)");
+        assert_eq!(
+            thrown(&db, "aggregate_all(?s, q(?x, ?y), ?n)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "aggregate_all(bogus, q(?x, ?y), ?n)"),
+            "domain_error(aggregate_spec, bogus)"
+        );
+        assert_eq!(
+            thrown(&db, "aggregate_all(sum(?y), q(?x, ?y), ?n)"),
+            "type_error(integer, a)"
+        );
+    }
+
+    #[test]
+    fn meta_call_and_cut() {
+        let db = db("
+            p(1). p(2).
+            pair(?x, ?y, ?x - ?y).
+            first(?x) :- p(?x), !.
+            local(?x) :- call((p(?x), !)).
+            local(3).
+            opaque(?x) :- p(?x), call(!).
+            branch(?x) :- (p(?x), ! ; ?x = 9).
+            choose(?x) :- (p(?x) -> true ; ?x = 0).
+            otherwise(?x) :- (fail -> ?x = a ; ?x = b).
+        ");
+        assert_eq!(answers(&db, "call(p, ?x).", "x"), ["1", "2"]);
+        assert_eq!(answers(&db, "call(pair(a), b, ?p).", "p"), ["a - b"]);
+        assert_eq!(answers(&db, "call(call, call, p, ?x).", "x"), ["1", "2"]);
+        assert_eq!(answers(&db, "once(p(?x)).", "x"), ["1"]);
+        assert_eq!(answers(&db, "ignore(p(?x)).", "x"), ["1"]);
+        assert_eq!(answers(&db, "ignore(fail), ?x = 1.", "x"), ["1"]);
+        assert_eq!(answers(&db, "forall(p(?x), ?x > 0), ?y = 1.", "y"), ["1"]);
+        assert_eq!(
+            answers(&db, "forall(p(?x), ?x > 1), ?y = 1.", "y"),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(answers(&db, "first(?x).", "x"), ["1"]);
+        assert_eq!(answers(&db, "local(?x).", "x"), ["1", "3"]);
+        assert_eq!(answers(&db, "opaque(?x).", "x"), ["1", "2"]);
+        assert_eq!(answers(&db, "branch(?x).", "x"), ["1"]);
+        assert_eq!(answers(&db, "choose(?x).", "x"), ["1"]);
+        assert_eq!(answers(&db, "otherwise(?x).", "x"), ["b"]);
+
+        assert_eq!(thrown(&db, "call(?g)"), "instantiation_error");
+        assert_eq!(thrown(&db, "call(?g, 1)"), "instantiation_error");
+        assert_eq!(thrown(&db, "call(1)"), "type_error(callable, 1)");
+        assert_eq!(thrown(&db, "call(1, a)"), "type_error(callable, 1)");
+        assert_eq!(thrown(&db, "once(?g)"), "instantiation_error");
+        assert_eq!(thrown(&db, "ignore(?g)"), "instantiation_error");
+        assert_eq!(thrown(&db, "forall(?g, true)"), "instantiation_error");
+        assert_eq!(
+            thrown(&db, "call(p, 1, 2)"),
+            "existence_error(procedure, p / 2)"
+        );
+    }
+
+    #[test]
+    fn list_library() {
+        let db = db("p.");
+        assert_eq!(answers(&db, "length([a, b, c], ?n).", "n"), ["3"]);
+        assert_eq!(answers(&db, "length(?l, 2).", "l").len(), 1);
+        assert_eq!(
+            answers(&db, "length([a | ?t], 3), ?t = [b, c].", "t"),
+            ["[b, c]"]
+        );
+        assert_eq!(answers(&db, "length([a, b | ?t], ?n), !.", "n"), ["2"]);
+        assert_eq!(answers(&db, "length(a, ?n).", "n"), Vec::<String>::new());
+        assert_eq!(answers(&db, "nth0(1, [a, b, c], ?e).", "e"), ["b"]);
+        assert_eq!(answers(&db, "nth1(1, [a, b, c], ?e).", "e"), ["a"]);
+        assert_eq!(answers(&db, "nth0(?i, [a, b], ?e).", "i"), ["0", "1"]);
+        assert_eq!(answers(&db, "nth0(5, [a], ?e).", "e"), Vec::<String>::new());
+        assert_eq!(
+            answers(&db, "append(?x, ?y, [1, 2]).", "x"),
+            ["[1, 2]", "[1]", "[]"]
+        );
+        assert_eq!(answers(&db, "member(?x, [a, b]).", "x"), ["a", "b"]);
+        assert_eq!(answers(&db, "reverse([1, 2, 3], ?r).", "r"), ["[3, 2, 1]"]);
+        assert_eq!(
+            answers(&db, "msort([b, a, c, a], ?l).", "l"),
+            ["[a, a, b, c]"]
+        );
+        assert_eq!(
+            answers(&db, "sort(0, @>=, [1, 3, 2, 3], ?l).", "l"),
+            ["[3, 3, 2, 1]"]
+        );
+        assert_eq!(answers(&db, "sort(0, @>, [b, a, b], ?l).", "l"), ["[b, a]"]);
+        assert_eq!(
+            answers(&db, "sort(1, @<, [f(2, a), f(1, b), f(2, c)], ?l).", "l"),
+            ["[f(1, b), f(2, a)]"]
+        );
+        assert_eq!(
+            answers(&db, "sort(2, @=<, [f(2, b), f(1, a), f(3, b)], ?l).", "l"),
+            ["[f(1, a), f(2, b), f(3, b)]"]
+        );
+
+        assert_eq!(thrown(&db, "length(?l, a)"), "type_error(integer, a)");
+        assert_eq!(thrown(&db, "nth1(a, [a], ?e)"), "type_error(integer, a)");
+        assert_eq!(thrown(&db, "msort(?l, ?s)"), "instantiation_error");
+        assert_eq!(thrown(&db, "msort([b | ?t], ?s)"), "instantiation_error");
+        assert_eq!(thrown(&db, "msort(a, ?s)"), "type_error(list, a)");
+        assert_eq!(
+            thrown(&db, "sort(a, @<, [1], ?l)"),
+            "type_error(integer, a)"
+        );
+        assert_eq!(
+            thrown(&db, "sort(0, bogus, [1], ?l)"),
+            "domain_error(order, bogus)"
+        );
+        assert_eq!(
+            thrown(&db, "sort(1, @<, [a], ?l)"),
+            "type_error(compound, a)"
+        );
+        assert_eq!(thrown(&db, "sort(0, @<, ?l, ?s)"), "instantiation_error");
+    }
+
+    #[test]
+    fn higher_order_library() {
+        let db = db("
+            pos(?x) :- ?x > 0.
+            small(?x) :- ?x < 3.
+            eq(?x, ?x).
+            wrap(?x, ?y) :- ?y = d(?x).
+            step(?x, ?y, ?a, ?b) :- ?b = s(?x, ?y, ?a).
+            five(?a, ?b, ?c, ?d, ?e, ?f) :- ?f = s(?a, ?b, ?c, ?d, ?e).
+        ");
+        assert_eq!(answers(&db, "maplist(pos, [1, 2]), ?x = 1.", "x"), ["1"]);
+        assert_eq!(
+            answers(&db, "maplist(pos, [1, 0]), ?x = 1.", "x"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            answers(&db, "maplist(wrap, [1, 2], ?l).", "l"),
+            ["[d(1), d(2)]"]
+        );
+        assert_eq!(
+            answers(&db, "maplist(wrap, ?l, [d(1), d(2)]).", "l"),
+            ["[1, 2]"]
+        );
+        assert_eq!(
+            answers(&db, "length(?l, 2), maplist(eq(z), ?l).", "l"),
+            ["[z, z]"]
+        );
+        assert_eq!(answers(&db, "maplist(eq, ?l, ?m), !.", "l"), ["[]"]);
+        assert_eq!(
+            answers(&db, "member(?x, [1, 2]), maplist(eq(?x), [?y]).", "y"),
+            ["1", "2"]
+        );
+        assert_eq!(
+            answers(&db, "maplist(five, [1], [2], [3], [4], [5], ?r).", "r"),
+            ["[s(1, 2, 3, 4, 5)]"]
+        );
+        assert_eq!(
+            answers(&db, "foldl(step, [1, 2], [a, b], 0, ?r).", "r"),
+            ["s(2, b, s(1, a, 0))"]
+        );
+        assert_eq!(
+            answers(&db, "include(small, [1, 2, 3, 4], ?l).", "l"),
+            ["[1, 2]"]
+        );
+        assert_eq!(
+            answers(&db, "exclude(small, [1, 2, 3, 4], ?l).", "l"),
+            ["[3, 4]"]
+        );
+        assert_eq!(
+            answers(&db, "partition(small, [1, 2, 3, 4], ?i, ?e).", "i"),
+            ["[1, 2]"]
+        );
+        assert_eq!(
+            answers(&db, "partition(small, [1, 2, 3, 4], ?i, ?e).", "e"),
+            ["[3, 4]"]
+        );
+
+        assert_eq!(thrown(&db, "maplist(?g, [1])"), "instantiation_error");
+        assert_eq!(thrown(&db, "include(?g, [1], ?l)"), "instantiation_error");
+        assert_eq!(thrown(&db, "maplist(pos, [a])"), "type_error(integer, a)");
+        assert_eq!(
+            thrown(&db, "maplist(eq, [1], [1], [1])"),
+            "existence_error(procedure, eq / 3)"
+        );
+    }
+
+    #[test]
+    fn type_checks() {
+        let db = db("p.");
+        let holds = |goal: &str| !db.query_all(&term(goal)).unwrap().is_empty();
+        for goal in [
+            "var(?x).",
+            "?x = ?y, var(?x).",
+            "nonvar(a).",
+            "number(1).",
+            "integer(1).",
+            "atom(a).",
+            "atom([]).",
+            "string(\"s\").",
+            "atomic(\"s\").",
+            "atomic(1).",
+            "compound(f(x)).",
+            "compound([a]).",
+            "callable(a).",
+            "callable(f(x)).",
+            "is_list([1, 2]).",
+            "is_list([]).",
+            "ground(f(a, [b])).",
+        ] {
+            assert!(holds(goal), "{goal}");
+        }
+        for goal in [
+            "var(a).",
+            "?x = 1, var(?x).",
+            "nonvar(?x).",
+            "number(a).",
+            "integer(\"1\").",
+            "atom(1).",
+            "atom(\"s\").",
+            "atom(f(a)).",
+            "string(a).",
+            "atomic(?x).",
+            "atomic(f(a)).",
+            "compound(a).",
+            "compound(\"s\").",
+            "callable(1).",
+            "callable(?x).",
+            "is_list(?x).",
+            "is_list([a | ?t]).",
+            "is_list(\"ab\").",
+            "ground(f(?x)).",
+            "ground([a, ?x]).",
+        ] {
+            assert!(!holds(goal), "{goal}");
+        }
+    }
+
+    #[test]
+    fn term_construction() {
+        let db = db("p.");
+        assert_eq!(answers(&db, "functor(f(a, b), ?n, ?a).", "n"), ["f"]);
+        assert_eq!(answers(&db, "functor(f(a, b), ?n, ?a).", "a"), ["2"]);
+        assert_eq!(answers(&db, "functor(a, ?n, ?a).", "a"), ["0"]);
+        assert_eq!(answers(&db, "functor(1, ?n, ?a).", "n"), ["1"]);
+        assert_eq!(answers(&db, "functor([a], ?n, ?a), ?n == '.'.", "a"), ["2"]);
+        assert_eq!(
+            answers(&db, "functor(?t, f, 2), ?t = f(a, b).", "t"),
+            ["f(a, b)"]
+        );
+        assert_eq!(answers(&db, "functor(?t, a, 0).", "t"), ["a"]);
+        assert_eq!(answers(&db, "arg(2, f(a, b), ?x).", "x"), ["b"]);
+        assert_eq!(answers(&db, "arg(?n, f(a, b), ?x).", "x"), ["a", "b"]);
+        assert_eq!(answers(&db, "arg(?n, f(a, b), b).", "n"), ["2"]);
+        assert_eq!(answers(&db, "arg(0, f(a), ?x).", "x"), Vec::<String>::new());
+        assert_eq!(
+            answers(&db, "arg(3, f(a, b), ?x).", "x"),
+            Vec::<String>::new()
+        );
+        assert_eq!(answers(&db, "arg(1, [a, b], ?x).", "x"), ["a"]);
+        assert_eq!(answers(&db, "f(a, b) =.. ?l.", "l"), ["[f, a, b]"]);
+        assert_eq!(answers(&db, "a =.. ?l.", "l"), ["[a]"]);
+        assert_eq!(answers(&db, "?t =.. [g, 1].", "t"), ["g(1)"]);
+        assert_eq!(answers(&db, "?t =.. [a].", "t"), ["a"]);
+        assert_eq!(
+            answers(
+                &db,
+                "copy_term(f(?x, ?y, ?x), f(?a, ?b, ?c)), ?a == ?c, ?a \\== ?b, ?a \\== ?x.",
+                "x"
+            ),
+            ["?x"]
+        );
+        assert_eq!(answers(&db, "copy_term(a, ?y).", "y"), ["a"]);
+
+        assert_eq!(thrown(&db, "functor(?t, ?n, 2)"), "instantiation_error");
+        assert_eq!(thrown(&db, "functor(?t, f, ?a)"), "instantiation_error");
+        assert_eq!(thrown(&db, "functor(?t, f, a)"), "type_error(integer, a)");
+        assert_eq!(
+            thrown(&db, "functor(?t, f(x), 1)"),
+            "type_error(atomic, f(x))"
+        );
+        assert_eq!(thrown(&db, "arg(1, ?t, ?x)"), "instantiation_error");
+        assert_eq!(thrown(&db, "arg(1, a, ?x)"), "type_error(compound, a)");
+        assert_eq!(thrown(&db, "arg(a, f(a), ?x)"), "type_error(integer, a)");
+        assert_eq!(thrown(&db, "?t =.. ?l"), "instantiation_error");
+        assert_eq!(thrown(&db, "?t =.. [f | ?r]"), "instantiation_error");
+        assert_eq!(thrown(&db, "?t =.. []"), "domain_error(non_empty_list, [])");
+        assert_eq!(thrown(&db, "?t =.. [f(x), 1]"), "type_error(atomic, f(x))");
+        assert_eq!(thrown(&db, "?t =.. [1, 2]"), "type_error(atom, 1)");
+    }
+
+    #[test]
+    fn standard_order() {
+        let db = db("p.");
+        assert_eq!(
+            answers(&db, "sort([f(a), \"s\", c, 1, ?x, b], ?l).", "l"),
+            ["[?x, 1, b, c, \"s\", f(a)]"]
+        );
+        assert_eq!(answers(&db, "compare(?o, 1, 2).", "o"), ["<"]);
+        assert_eq!(answers(&db, "compare(?o, a, a).", "o"), ["="]);
+        assert_eq!(answers(&db, "compare(?o, f(b), f(a, a)).", "o"), ["<"]);
+        assert_eq!(answers(&db, "compare(?o, f(a, b), f(a, a)).", "o"), [">"]);
+        assert_eq!(answers(&db, "compare(?o, g(a), f(a)).", "o"), [">"]);
+        assert_eq!(
+            answers(&db, "compare(<, b, a), ?x = 1.", "x"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            answers(
+                &db,
+                "a @< b, f(a) @> a, 1 @=< 1, a @>= a, \"a\" @< \"b\", ?x = 1.",
+                "x"
+            ),
+            ["1"]
+        );
+        assert_eq!(answers(&db, "f(?x) == f(?x).", "x"), ["?x"]);
+        assert_eq!(answers(&db, "f(?x) == f(?y).", "x"), Vec::<String>::new());
+        assert_eq!(answers(&db, "f(?x) \\== f(?y).", "x"), ["?x"]);
+        assert_eq!(answers(&db, "sort([b, a, c, a], ?l).", "l"), ["[a, b, c]"]);
+        assert_eq!(
+            answers(&db, "keysort([b - 1, a - 2, b - 0], ?l).", "l"),
+            ["[a - 2, b - 1, b - 0]"]
+        );
+        assert_eq!(answers(&db, "keysort([], ?l).", "l"), ["[]"]);
+
+        assert_eq!(thrown(&db, "compare(1, a, b)"), "type_error(atom, 1)");
+        assert_eq!(
+            thrown(&db, "compare(foo, a, b)"),
+            "domain_error(order, foo)"
+        );
+        assert_eq!(thrown(&db, "sort(a, ?l)"), "type_error(list, a)");
+        assert_eq!(thrown(&db, "sort([b | ?t], ?l)"), "instantiation_error");
+        assert_eq!(thrown(&db, "keysort(a, ?l)"), "type_error(list, a)");
+        assert_eq!(thrown(&db, "keysort([a], ?l)"), "type_error(pair, a)");
+        assert_eq!(
+            thrown(&db, "keysort([b - 1 | ?t], ?l)"),
+            "instantiation_error"
+        );
+    }
+
+    #[test]
+    fn text_builtins() {
+        let db = db("p.");
+        assert_eq!(answers(&db, "atom_length(hello, ?n).", "n"), ["5"]);
+        assert_eq!(answers(&db, "atom_length(héllo, ?n).", "n"), ["5"]);
+        assert_eq!(answers(&db, "atom_length(12, ?n).", "n"), ["2"]);
+        assert_eq!(answers(&db, "atom_concat(ab, cd, ?x).", "x"), ["abcd"]);
+        assert_eq!(answers(&db, "atom_concat(a, 1, ?x).", "x"), ["a1"]);
+        assert_eq!(
+            answers(&db, "atom_concat(?x, ?y, ab).", "x"),
+            ["''", "a", "ab"]
+        );
+        assert_eq!(answers(&db, "atom_concat(?x, b, ab).", "x"), ["a"]);
+        assert_eq!(
+            answers(&db, "sub_atom(abc, ?b, 2, ?a, ?s).", "s"),
+            ["ab", "bc"]
+        );
+        assert_eq!(
+            answers(&db, "sub_atom(abc, ?b, ?l, 0, ?s).", "s"),
+            ["''", "abc", "bc", "c"]
+        );
+        assert_eq!(answers(&db, "sub_atom(héllo, 1, 1, ?a, ?s).", "s"), ["é"]);
+        assert_eq!(answers(&db, "sub_atom(héllo, 1, 1, ?a, ?s).", "a"), ["3"]);
+        assert_eq!(
+            answers(&db, "string_concat(\"ab\", \"cd\", ?s).", "s"),
+            ["\"abcd\""]
+        );
+        assert_eq!(answers(&db, "string_concat(a, b, ?s).", "s"), ["\"ab\""]);
+        assert_eq!(
+            answers(&db, "string_concat(?x, ?y, \"ab\").", "y"),
+            ["\"\"", "\"ab\"", "\"b\""]
+        );
+        assert_eq!(
+            answers(&db, "split_string(\"a,b,,c\", \",\", \"\", ?l).", "l"),
+            ["[\"a\", \"b\", \"\", \"c\"]"]
+        );
+        assert_eq!(
+            answers(&db, "split_string(\"  a  \", \"\", \" \", ?l).", "l"),
+            ["[\"a\"]"]
+        );
+        assert_eq!(answers(&db, "atom_string(abc, ?s).", "s"), ["\"abc\""]);
+        assert_eq!(answers(&db, "atom_string(?a, \"xy\").", "a"), ["xy"]);
+        assert_eq!(answers(&db, "number_codes(12, ?c).", "c"), ["[49, 50]"]);
+        assert_eq!(answers(&db, "number_codes(?n, [0'1, 0'2]).", "n"), ["12"]);
+        assert_eq!(answers(&db, "atom_chars(abc, ?l).", "l"), ["[a, b, c]"]);
+        assert_eq!(answers(&db, "atom_chars(?a, [x, y]).", "a"), ["xy"]);
+        assert_eq!(answers(&db, "upcase_atom(hello, ?u).", "u"), ["'HELLO'"]);
+        assert_eq!(answers(&db, "upcase_atom(straße, ?u).", "u"), ["'STRASSE'"]);
+        assert_eq!(answers(&db, "string_code(2, \"héllo\", ?c).", "c"), ["233"]);
+        assert_eq!(
+            answers(&db, "string_code(4, \"abc\", ?c).", "c"),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(thrown(&db, "atom_length(?a, ?n)"), "instantiation_error");
+        assert_eq!(
+            thrown(&db, "atom_length(f(x), ?n)"),
+            "type_error(atomic, f(x))"
+        );
+        assert_eq!(thrown(&db, "atom_length(a, b)"), "type_error(integer, b)");
+        assert_eq!(
+            thrown(&db, "atom_concat(?x, ?y, ?z)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "atom_concat(f(x), a, ?z)"),
+            "type_error(atomic, f(x))"
+        );
+        assert_eq!(
+            thrown(&db, "sub_atom(?x, ?b, ?l, ?a, ?s)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "sub_atom(f(x), ?b, ?l, ?a, ?s)"),
+            "type_error(atomic, f(x))"
+        );
+        assert_eq!(
+            thrown(&db, "string_concat(?x, ?y, ?z)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "split_string(?s, \",\", \"\", ?l)"),
+            "instantiation_error"
+        );
+        assert_eq!(thrown(&db, "atom_string(?a, ?s)"), "instantiation_error");
+        assert_eq!(thrown(&db, "number_codes(?n, ?c)"), "instantiation_error");
+        assert_eq!(
+            thrown(&db, "number_codes(?n, [0'a])"),
+            "syntax_error(illegal_number)"
+        );
+        assert_eq!(thrown(&db, "atom_chars(?a, ?l)"), "instantiation_error");
+        assert_eq!(
+            thrown(&db, "atom_chars(?a, [x, ?y])"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "atom_chars(?a, [x, f(y)])"),
+            "type_error(character, f(y))"
+        );
+        assert_eq!(thrown(&db, "upcase_atom(?a, ?u)"), "instantiation_error");
+        assert_eq!(
+            thrown(&db, "string_code(?i, \"abc\", ?c)"),
+            "instantiation_error"
+        );
+        assert_eq!(
+            thrown(&db, "string_code(a, \"abc\", ?c)"),
+            "type_error(integer, a)"
+        );
     }
 }
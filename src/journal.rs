@@ -0,0 +1,411 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::parser::{Clause, Term};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"PLJS";
+const SNAPSHOT_VERSION: u8 = 1;
+const COMPACT_AFTER: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Asserta(Clause),
+    Assertz(Clause),
+    Retract(Clause),
+}
+
+impl Op {
+    pub fn clause(&self) -> &Clause {
+        match self {
+            Op::Asserta(clause) | Op::Assertz(clause) | Op::Retract(clause) => clause,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    file: File,
+    seq: u64,
+    records: usize,
+}
+
+pub struct Recovered {
+    pub snapshot: Option<Vec<Clause>>,
+    pub ops: Vec<Op>,
+}
+
+impl Journal {
+    pub fn open(dir: &Path) -> io::Result<(Journal, Recovered)> {
+        fs::create_dir_all(dir)?;
+
+        let (snapshot_seq, snapshot) = match fs::read(dir.join("snapshot")) {
+            Ok(bytes) => {
+                let (seq, clauses) = read_snapshot(&bytes)?;
+                (seq, Some(clauses))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (0, None),
+            Err(err) => return Err(err),
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join("journal"))?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let mut seq = snapshot_seq;
+        let mut ops = vec![];
+        let mut records = 0;
+        let mut valid = 0;
+        while let Some((record_seq, record_ops, len)) = read_record(&bytes[valid..]) {
+            valid += len;
+            records += 1;
+            if record_seq > snapshot_seq {
+                seq = record_seq;
+                ops.extend(record_ops);
+            }
+        }
+        if valid < bytes.len() {
+            file.set_len(valid as u64)?;
+            file.sync_all()?;
+        }
+
+        let journal = Journal {
+            dir: dir.to_path_buf(),
+            file,
+            seq,
+            records,
+        };
+        Ok((journal, Recovered { snapshot, ops }))
+    }
+
+    pub fn append(&mut self, ops: &[Op]) -> io::Result<()> {
+        self.seq += 1;
+        let mut payload = vec![];
+        write_u64(&mut payload, self.seq);
+        write_u32(&mut payload, ops.len() as u32);
+        for op in ops {
+            let tag = match op {
+                Op::Asserta(_) => 0,
+                Op::Assertz(_) => 1,
+                Op::Retract(_) => 2,
+            };
+            payload.push(tag);
+            write_value(&mut payload, &op.clause().to_term());
+        }
+
+        let mut record = vec![];
+        write_u32(&mut record, payload.len() as u32);
+        write_u32(&mut record, crc32(&payload));
+        record.extend(payload);
+        let len = self.file.metadata()?.len();
+        if let Err(err) = self
+            .file
+            .write_all(&record)
+            .and_then(|_| self.file.sync_data())
+        {
+            self.seq -= 1;
+            self.file.set_len(len).ok();
+            return Err(err);
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.records >= COMPACT_AFTER
+    }
+
+    pub fn compact(&mut self, clauses: &[Clause]) -> io::Result<()> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.push(SNAPSHOT_VERSION);
+        write_u64(&mut bytes, self.seq);
+        write_u32(&mut bytes, clauses.len() as u32);
+        for clause in clauses {
+            write_value(&mut bytes, &clause.to_term());
+        }
+        let checksum = crc32(&bytes);
+        write_u32(&mut bytes, checksum);
+
+        let tmp = self.dir.join("snapshot.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join("snapshot"))?;
+        if let Ok(dir) = File::open(&self.dir) {
+            dir.sync_all().ok();
+        }
+
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+fn read_snapshot(bytes: &[u8]) -> io::Result<(u64, Vec<Clause>)> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt journal snapshot");
+    if bytes.len() < 4 || &bytes[..4] != SNAPSHOT_MAGIC {
+        return Err(corrupt());
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32(body).to_le_bytes() != checksum {
+        return Err(corrupt());
+    }
+
+    let mut reader = Reader::new(&body[4..]);
+    if reader.u8()? != SNAPSHOT_VERSION {
+        return Err(corrupt());
+    }
+    let seq = reader.u64()?;
+    let count = reader.u32()?;
+    let clauses = (0..count)
+        .map(|_| reader.value().map(Clause::from_term))
+        .collect::<io::Result<_>>()?;
+    Ok((seq, clauses))
+}
+
+fn read_record(bytes: &[u8]) -> Option<(u64, Vec<Op>, usize)> {
+    let mut header = Reader::new(bytes);
+    let len = header.u32().ok()? as usize;
+    let checksum = header.u32().ok()?;
+    let payload = bytes.get(8..8 + len)?;
+    if crc32(payload) != checksum {
+        return None;
+    }
+
+    let mut reader = Reader::new(payload);
+    let seq = reader.u64().ok()?;
+    let count = reader.u32().ok()?;
+    let mut ops = vec![];
+    for _ in 0..count {
+        let tag = reader.u8().ok()?;
+        let clause = Clause::from_term(reader.value().ok()?);
+        ops.push(match tag {
+            0 => Op::Asserta(clause),
+            1 => Op::Assertz(clause),
+            2 => Op::Retract(clause),
+            _ => return None,
+        });
+    }
+    Some((seq, ops, 8 + len))
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn write_u32(out: &mut Vec<u8>, val: u32) {
+    out.extend(val.to_le_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend(val.to_le_bytes());
+}
+
+pub fn write_str(out: &mut Vec<u8>, val: &str) {
+    write_u32(out, val.len() as u32);
+    out.extend(val.as_bytes());
+}
+
+pub fn write_value(out: &mut Vec<u8>, value: &Term) {
+    match value {
+        Term::CompoundTerm(name, args) => {
+            out.push(0);
+            write_str(out, name);
+            write_values(out, args);
+        }
+        Term::List(values) => {
+            out.push(2);
+            write_values(out, values);
+        }
+        Term::Cons(left, right) => {
+            out.push(3);
+            write_value(out, left);
+            write_value(out, right);
+        }
+        Term::Str(val) => {
+            out.push(4);
+            write_str(out, val);
+        }
+        Term::Int(val) => {
+            out.push(5);
+            write_u64(out, *val as u64);
+        }
+        Term::Variable(name) => {
+            out.push(6);
+            write_str(out, name);
+        }
+    }
+}
+
+fn write_values(out: &mut Vec<u8>, values: &[Term]) {
+    write_u32(out, values.len() as u32);
+    for value in values {
+        write_value(out, value);
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    idx: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, idx: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        match self.bytes.get(self.idx..self.idx + len) {
+            Some(bytes) => {
+                self.idx += len;
+                Ok(bytes)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated value",
+            )),
+        }
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn str(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn values(&mut self) -> io::Result<Vec<Term>> {
+        let len = self.u32()?;
+        (0..len).map(|_| self.value()).collect()
+    }
+
+    pub fn value(&mut self) -> io::Result<Term> {
+        Ok(match self.u8()? {
+            0 => Term::CompoundTerm(self.str()?, self.values()?),
+            2 => Term::List(self.values()?),
+            3 => Term::Cons(Box::new(self.value()?), Box::new(self.value()?)),
+            4 => Term::Str(self.str()?),
+            5 => Term::Int(self.u64()? as usize),
+            6 => Term::Variable(self.str()?),
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown value tag {}", tag),
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(n: usize) -> Clause {
+        Clause::from_term(Term::CompoundTerm("item".to_string(), vec![Term::Int(n)]))
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("journal-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn reopen_after_torn_write() {
+        let dir = temp_dir("torn");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        journal.append(&[Op::Assertz(item(1))]).unwrap();
+        journal
+            .append(&[Op::Assertz(item(2)), Op::Retract(item(1))])
+            .unwrap();
+        let valid = fs::metadata(dir.join("journal")).unwrap().len();
+        drop(journal);
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join("journal"))
+            .unwrap();
+        file.write_all(&[40, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(file);
+
+        let (mut journal, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(
+            recovered.ops,
+            [
+                Op::Assertz(item(1)),
+                Op::Assertz(item(2)),
+                Op::Retract(item(1))
+            ]
+        );
+        assert_eq!(fs::metadata(dir.join("journal")).unwrap().len(), valid);
+
+        journal.append(&[Op::Asserta(item(3))]).unwrap();
+        drop(journal);
+        let (_, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered.ops.len(), 4);
+        assert_eq!(recovered.ops[3], Op::Asserta(item(3)));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reopen_after_corrupt_checksum() {
+        let dir = temp_dir("checksum");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        journal.append(&[Op::Assertz(item(1))]).unwrap();
+        journal.append(&[Op::Assertz(item(2))]).unwrap();
+        drop(journal);
+
+        let mut bytes = fs::read(dir.join("journal")).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(dir.join("journal"), &bytes).unwrap();
+
+        let (_, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered.ops, [Op::Assertz(item(1))]);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,58 +1,39 @@
+use std::{iter::Peekable, str::CharIndices};
+
+use crate::error::Error;
+
+const SYMBOL_CHARS: &str = "+-*/\\^<>=~:.?@#&$";
+
 #[derive(Debug)]
 pub enum Token {
     Id(String),
+    QuotedId(String),
     Int(usize),
     Str(String),
     Variable(String),
     Comma,
     Dot,
     OpenParen,
+    FunctorOpenParen,
     CloseParen,
-    BackArrow,
     OpenSqBrace,
     CloseSqBrace,
     Underscore,
-    Eq,
-    GreaterThan,
-    LessThan,
-    GreaterThanEqual,
-    LessThanEqual,
+    Bar,
 }
 
 impl Token {
     pub fn as_id(&self) -> Option<String> {
         match self {
-            Token::Id(name) => Some(name.to_owned()),
-            _ => None,
-        }
-    }
-    pub fn as_eq(&self) -> Option<()> {
-        match self {
-            Token::Eq => Some(()),
-            _ => None,
-        }
-    }
-    pub fn as_greater_than(&self) -> Option<()> {
-        match self {
-            Token::GreaterThan => Some(()),
-            _ => None,
-        }
-    }
-    pub fn as_less_than(&self) -> Option<()> {
-        match self {
-            Token::LessThan => Some(()),
+            Token::Id(name) | Token::QuotedId(name) => Some(name.to_owned()),
             _ => None,
         }
     }
-    pub fn as_greater_than_equal(&self) -> Option<()> {
+    pub fn as_capitalized_id(&self) -> Option<String> {
         match self {
-            Token::GreaterThanEqual => Some(()),
-            _ => None,
-        }
-    }
-    pub fn as_less_than_equal(&self) -> Option<()> {
-        match self {
-            Token::LessThanEqual => Some(()),
+            Token::Id(name) if name.starts_with(|c: char| c.is_uppercase() || c == '_') => {
+                Some(name.to_owned())
+            }
             _ => None,
         }
     }
@@ -93,6 +74,12 @@ impl Token {
             _ => None,
         }
     }
+    pub fn as_functor_open_paren(&self) -> Option<()> {
+        match self {
+            Token::FunctorOpenParen => Some(()),
+            _ => None,
+        }
+    }
     pub fn as_open_paren(&self) -> Option<()> {
         match self {
             Token::OpenParen => Some(()),
@@ -111,131 +98,289 @@ impl Token {
             _ => None,
         }
     }
-    pub fn as_back_arrow(&self) -> Option<()> {
+    pub fn as_underscore(&self) -> Option<()> {
         match self {
-            Token::BackArrow => Some(()),
+            Token::Underscore => Some(()),
             _ => None,
         }
     }
-    pub fn as_underscore(&self) -> Option<()> {
+    pub fn as_bar(&self) -> Option<()> {
         match self {
-            Token::Underscore => Some(()),
+            Token::Bar => Some(()),
             _ => None,
         }
     }
 }
 
-pub fn tokenize(program_string: String) -> Vec<Token> {
-    let mut idx = 0;
-    let mut tokens: Vec<Token> = vec![];
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
 
-    while idx < program_string.len() {
-        if [Some(" "), Some("\n")].contains(&program_string.get(idx..=idx)) {
-            idx += 1;
-        } else if program_string.get(idx..=idx) == Some("(") {
-            idx += 1;
-            tokens.push(Token::OpenParen)
-        } else if program_string.get(idx..=idx) == Some("_") {
-            idx += 1;
-            tokens.push(Token::Underscore)
-        } else if program_string.get(idx..=idx) == Some(")") {
-            idx += 1;
-            tokens.push(Token::CloseParen)
-        } else if program_string.get(idx..=idx) == Some("=") {
-            idx += 1;
-            tokens.push(Token::Eq)
-        } else if program_string.get(idx..=idx) == Some(".") {
-            idx += 1;
-            tokens.push(Token::Dot)
-        } else if program_string.get(idx..=idx) == Some(",") {
-            idx += 1;
-            tokens.push(Token::Comma)
-        } else if program_string.get(idx..=idx + 1) == Some(":-") {
-            idx += 2;
-            tokens.push(Token::BackArrow)
-        } else if program_string.get(idx..=idx) == Some("[") {
-            idx += 1;
-            tokens.push(Token::OpenSqBrace)
-        } else if program_string.get(idx..=idx) == Some("]") {
-            idx += 1;
-            tokens.push(Token::CloseSqBrace)
-        } else if program_string.get(idx..=idx + 1) == Some(">=") {
-            idx += 2;
-            tokens.push(Token::GreaterThanEqual)
-        } else if program_string.get(idx..=idx + 1) == Some("<=") {
-            idx += 2;
-            tokens.push(Token::LessThanEqual)
-        } else if program_string.get(idx..=idx) == Some(">") {
-            idx += 1;
-            tokens.push(Token::GreaterThan)
-        } else if program_string.get(idx..=idx) == Some("<") {
-            idx += 1;
-            tokens.push(Token::LessThan)
-        } else if program_string
-            .get(idx..=idx)
-            .filter(|char| char.chars().next().unwrap().is_numeric())
-            .is_some()
-        {
-            let mut num: String = "".to_string();
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            chars: source.char_indices().peekable(),
+        }
+    }
 
-            for char in program_string
-                .chars()
-                .skip(idx)
-                .take_while(|char| char.is_numeric())
-            {
-                num += char.to_string().as_str();
+    fn offset(&mut self) -> usize {
+        match self.chars.peek() {
+            Some((idx, _)) => *idx,
+            None => self.source.len(),
+        }
+    }
+
+    fn rest(&mut self) -> &'a str {
+        let offset = self.offset();
+        &self.source[offset..]
+    }
+
+    fn skip(&mut self, len: usize) {
+        let end = self.offset() + len;
+        while self.offset() < end {
+            self.chars.next();
+        }
+    }
+
+    fn take_while(&mut self, cond: impl Fn(char) -> bool) -> &'a str {
+        let start = self.offset();
+        while self.chars.next_if(|(_, c)| cond(*c)).is_some() {}
+        let end = self.offset();
+        &self.source[start..end]
+    }
+
+    fn quoted(&mut self, quote: char) -> Result<String, Error> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, c)) if c == quote => match self.chars.next_if(|(_, c)| *c == quote) {
+                    Some(_) => text.push(quote),
+                    None => return Ok(text),
+                },
+                Some((_, '\\')) => text.extend(self.escape()?),
+                Some((_, c)) => text.push(c),
+                None => return Err(Error::Syntax(format!("unterminated quoted {:?}", text))),
             }
-            idx += num.len();
-            tokens.push(Token::Int(num.parse().unwrap()))
-        } else if &program_string[idx..=idx] == "?" {
-            let mut name: String = "".to_string();
-            idx += 1;
-
-            for char in program_string
-                .chars()
-                .skip(idx)
-                .take_while(|c| c.is_alphanumeric())
-            {
-                name += char.to_string().as_str();
+        }
+    }
+
+    fn escape(&mut self) -> Result<Option<char>, Error> {
+        let escaped = match self.chars.next() {
+            Some((_, '\n')) => return Ok(None),
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, 'a')) => '\x07',
+            Some((_, 'b')) => '\x08',
+            Some((_, 'f')) => '\x0c',
+            Some((_, 'v')) => '\x0b',
+            Some((_, 'x')) => self.numeric_escape(String::new(), 16)?,
+            Some((_, c @ '0'..='7')) => self.numeric_escape(c.to_string(), 8)?,
+            Some((_, c @ ('\\' | '\'' | '"' | '`'))) => c,
+            escape => {
+                let escape = escape.map(|(_, c)| c);
+                return Err(Error::Syntax(format!(
+                    "undefined escape sequence {:?}",
+                    escape
+                )));
             }
+        };
+        Ok(Some(escaped))
+    }
 
-            idx += name.len();
-            tokens.push(Token::Variable(name))
-        } else if &program_string[idx..=idx] == "\"" {
-            let mut str: String = "".to_string();
-            idx += 1;
+    fn numeric_escape(&mut self, mut digits: String, radix: u32) -> Result<char, Error> {
+        digits.push_str(self.take_while(|c| c.is_digit(radix)));
+        if self.chars.next_if(|(_, c)| *c == '\\').is_none() {
+            return Err(Error::Syntax(format!(
+                "unterminated numeric escape {:?}",
+                digits
+            )));
+        }
+        u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| Error::Syntax(format!("invalid character code {:?}", digits)))
+    }
 
-            for char in program_string
-                .chars()
-                .skip(idx)
-                .take_while(|char| *char != '"')
-            {
-                str += char.to_string().as_str();
+    fn char_code(&mut self) -> Result<usize, Error> {
+        let code = match self.chars.next() {
+            Some((_, '\\')) => self
+                .escape()?
+                .ok_or_else(|| Error::Syntax("line continuation in character code".to_string()))?,
+            Some((_, '\'')) => {
+                self.chars.next_if(|(_, c)| *c == '\'');
+                '\''
             }
-            idx += str.len() + 1;
-
-            tokens.push(Token::Str(str))
-        } else if program_string[idx..=idx]
-            .chars()
-            .next()
-            .unwrap()
-            .is_alphabetic()
-        {
-            let mut name: String = "".to_string();
+            Some((_, c)) => c,
+            None => return Err(Error::Syntax("missing character code".to_string())),
+        };
+        Ok(code as usize)
+    }
 
-            for char in program_string
-                .chars()
-                .skip(idx)
-                .take_while(|c| c.is_alphanumeric() || c == &'_')
+    fn symbol(&mut self) -> Option<Token> {
+        let rest = self.rest();
+        let (len, token) = match rest.chars().next()? {
+            '(' => (1, Token::OpenParen),
+            ')' => (1, Token::CloseParen),
+            ',' => (1, Token::Comma),
+            '[' => (1, Token::OpenSqBrace),
+            ']' => (1, Token::CloseSqBrace),
+            '|' => (1, Token::Bar),
+            c @ ('!' | ';') => (1, Token::Id(c.to_string())),
+            '.' if rest[1..].starts_with(|c: char| c.is_whitespace() || c == '%')
+                || rest.len() == 1 =>
             {
-                name += char.to_string().as_str();
+                (1, Token::Dot)
+            }
+            _ => {
+                let len = rest
+                    .char_indices()
+                    .find(|(idx, c)| {
+                        !SYMBOL_CHARS.contains(*c)
+                            || (*c == '?'
+                                && rest[idx + 1..].starts_with(|c: char| c.is_alphanumeric()))
+                    })
+                    .map_or(rest.len(), |(idx, _)| idx);
+                if len == 0 {
+                    return None;
+                }
+                (len, Token::Id(rest[..len].to_string()))
             }
+        };
+        self.skip(len);
+        Some(token)
+    }
+}
 
-            idx += name.len();
-            tokens.push(Token::Id(name))
+pub fn tokenize(program_string: String) -> Result<Vec<Token>, Error> {
+    let mut lexer = Lexer::new(&program_string);
+    let mut tokens: Vec<Token> = vec![];
+    let mut layout = true;
+
+    while let Some(&(_, char)) = lexer.chars.peek() {
+        let follows_name =
+            !layout && matches!(tokens.last(), Some(Token::Id(_) | Token::QuotedId(_)));
+        layout = false;
+        if char.is_whitespace() {
+            layout = true;
+            lexer.chars.next();
+        } else if char == '%' {
+            layout = true;
+            lexer.take_while(|c| c != '\n');
+        } else if lexer.rest().starts_with("/*") {
+            layout = true;
+            match lexer.rest().find("*/") {
+                Some(end) => lexer.skip(end + 2),
+                None => return Err(Error::Syntax("unterminated block comment".to_string())),
+            }
+        } else if lexer.rest().starts_with("0'") {
+            lexer.skip(2);
+            tokens.push(Token::Int(lexer.char_code()?))
+        } else if let Some(token) = lexer.symbol() {
+            match token {
+                Token::OpenParen if follows_name => tokens.push(Token::FunctorOpenParen),
+                token => tokens.push(token),
+            }
+        } else if char.is_ascii_digit() {
+            let num = lexer.take_while(|c| c.is_ascii_digit());
+            match num.parse() {
+                Ok(num) => tokens.push(Token::Int(num)),
+                Err(_) => return Err(Error::Syntax(format!("integer out of range {}", num))),
+            }
+        } else if char == '?' {
+            lexer.chars.next();
+            let name = lexer.take_while(|c| c.is_alphanumeric());
+            tokens.push(Token::Variable(name.to_string()))
+        } else if char == '"' {
+            lexer.chars.next();
+            tokens.push(Token::Str(lexer.quoted('"')?))
+        } else if char == '\'' {
+            lexer.chars.next();
+            tokens.push(Token::QuotedId(lexer.quoted('\'')?))
+        } else if char.is_alphabetic()
+            || (char == '_' && lexer.rest()[1..].starts_with(|c: char| c.is_alphanumeric()))
+        {
+            let name = lexer.take_while(|c| c.is_alphanumeric() || c == '_');
+            tokens.push(Token::Id(name.to_string()))
+        } else if char == '_' {
+            lexer.chars.next();
+            tokens.push(Token::Underscore)
         } else {
-            panic!("no token found!")
+            return Err(Error::Syntax(format!(
+                "no token found at {:?}",
+                lexer.rest()
+            )));
         }
     }
-    tokens
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source.to_string()).unwrap()
+    }
+
+    #[test]
+    fn unicode_atoms_and_variables() {
+        let tokens = tokens("größe(?ñame, 'naïve', δ).");
+        assert!(matches!(&tokens[0], Token::Id(name) if name == "größe"));
+        assert!(matches!(tokens[1], Token::FunctorOpenParen));
+        assert!(matches!(&tokens[2], Token::Variable(name) if name == "ñame"));
+        assert!(matches!(&tokens[4], Token::QuotedId(name) if name == "naïve"));
+        assert!(matches!(&tokens[6], Token::Id(name) if name == "δ"));
+        assert!(matches!(tokens[7], Token::CloseParen));
+        assert!(matches!(tokens[8], Token::Dot));
+    }
+
+    #[test]
+    fn comments_are_layout() {
+        let tokens = tokens("a % line comment\n /* block\n comment */ (b). c/* x */(d).");
+        assert!(matches!(&tokens[0], Token::Id(name) if name == "a"));
+        assert!(matches!(tokens[1], Token::OpenParen));
+        assert!(matches!(&tokens[5], Token::Id(name) if name == "c"));
+        assert!(matches!(tokens[6], Token::OpenParen));
+        assert!(matches!(
+            tokenize("a /* open".to_string()),
+            Err(Error::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn string_escapes() {
+        let tokens = tokens(
+            r#""a\nb\t\\\"\x41\\101\ \
+c""#,
+        );
+        assert!(matches!(&tokens[0], Token::Str(text) if text == "a\nb\t\\\"AA c"));
+        assert!(matches!(
+            tokenize(r#""\q""#.to_string()),
+            Err(Error::Syntax(_))
+        ));
+        assert!(matches!(
+            tokenize(r#""\x41""#.to_string()),
+            Err(Error::Syntax(_))
+        ));
+        assert!(matches!(
+            tokenize(r#""open"#.to_string()),
+            Err(Error::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn quoted_atoms_and_character_codes() {
+        let tokens = tokens("'it''s' 'a\\'b' '' 0'a 0''' 0'\\n 0' ");
+        assert!(matches!(&tokens[0], Token::QuotedId(name) if name == "it's"));
+        assert!(matches!(&tokens[1], Token::QuotedId(name) if name == "a'b"));
+        assert!(matches!(&tokens[2], Token::QuotedId(name) if name.is_empty()));
+        assert!(matches!(tokens[3], Token::Int(97)));
+        assert!(matches!(tokens[4], Token::Int(39)));
+        assert!(matches!(tokens[5], Token::Int(10)));
+        assert!(matches!(tokens[6], Token::Int(32)));
+        assert!(matches!(tokenize("0'".to_string()), Err(Error::Syntax(_))));
+    }
 }
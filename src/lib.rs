@@ -0,0 +1,11 @@
+pub mod compiler;
+pub mod datalog;
+pub mod error;
+pub mod interpreter;
+pub mod journal;
+pub mod lexer;
+pub mod library;
+pub mod operators;
+pub mod parser;
+pub mod snapshot;
+pub mod vm;
@@ -0,0 +1,29 @@
+use std::sync::OnceLock;
+
+use crate::{
+    lexer,
+    parser::{Clause, Item, Parser},
+};
+
+const SOURCES: &[&str] = &[
+    include_str!("library/lists.pl"),
+    include_str!("library/apply.pl"),
+];
+
+pub fn clauses() -> &'static [Clause] {
+    static CLAUSES: OnceLock<Vec<Clause>> = OnceLock::new();
+    CLAUSES.get_or_init(|| {
+        SOURCES
+            .iter()
+            .flat_map(|source| {
+                lexer::tokenize(source.to_string())
+                    .and_then(|tokens| Parser::new(tokens).parse())
+                    .unwrap_or_else(|err| panic!("invalid library source: {}", err))
+            })
+            .map(|item| match item {
+                Item::Clause(clause) => clause,
+                item => panic!("unexpected item in library {:?}", item),
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,47 @@
+maplist(?g, []).
+maplist(?g, [?a|?as]) :- call(?g, ?a), maplist(?g, ?as).
+
+maplist(?g, [], []).
+maplist(?g, [?a|?as], [?b|?bs]) :- call(?g, ?a, ?b), maplist(?g, ?as, ?bs).
+
+maplist(?g, [], [], []).
+maplist(?g, [?a|?as], [?b|?bs], [?c|?cs]) :-
+    call(?g, ?a, ?b, ?c), maplist(?g, ?as, ?bs, ?cs).
+
+maplist(?g, [], [], [], []).
+maplist(?g, [?a|?as], [?b|?bs], [?c|?cs], [?d|?ds]) :-
+    call(?g, ?a, ?b, ?c, ?d), maplist(?g, ?as, ?bs, ?cs, ?ds).
+
+maplist(?g, [], [], [], [], []).
+maplist(?g, [?a|?as], [?b|?bs], [?c|?cs], [?d|?ds], [?e|?es]) :-
+    call(?g, ?a, ?b, ?c, ?d, ?e), maplist(?g, ?as, ?bs, ?cs, ?ds, ?es).
+
+maplist(?g, [], [], [], [], [], []).
+maplist(?g, [?a|?as], [?b|?bs], [?c|?cs], [?d|?ds], [?e|?es], [?f|?fs]) :-
+    call(?g, ?a, ?b, ?c, ?d, ?e, ?f), maplist(?g, ?as, ?bs, ?cs, ?ds, ?es, ?fs).
+
+foldl(?g, [], ?v, ?v).
+foldl(?g, [?a|?as], ?v0, ?v) :- call(?g, ?a, ?v0, ?v1), foldl(?g, ?as, ?v1, ?v).
+
+foldl(?g, [], [], ?v, ?v).
+foldl(?g, [?a|?as], [?b|?bs], ?v0, ?v) :-
+    call(?g, ?a, ?b, ?v0, ?v1), foldl(?g, ?as, ?bs, ?v1, ?v).
+
+foldl(?g, [], [], [], ?v, ?v).
+foldl(?g, [?a|?as], [?b|?bs], [?c|?cs], ?v0, ?v) :-
+    call(?g, ?a, ?b, ?c, ?v0, ?v1), foldl(?g, ?as, ?bs, ?cs, ?v1, ?v).
+
+include(?p, [], []).
+include(?p, [?a|?as], ?r) :-
+    ( call(?p, ?a) -> ?r = [?a|?t] ; ?r = ?t ),
+    include(?p, ?as, ?t).
+
+exclude(?p, [], []).
+exclude(?p, [?a|?as], ?r) :-
+    ( call(?p, ?a) -> ?r = ?t ; ?r = [?a|?t] ),
+    exclude(?p, ?as, ?t).
+
+partition(?p, [], [], []).
+partition(?p, [?a|?as], ?i, ?e) :-
+    ( call(?p, ?a) -> ?i = [?a|?is], ?e = ?es ; ?i = ?is, ?e = [?a|?es] ),
+    partition(?p, ?as, ?is, ?es).
@@ -0,0 +1,21 @@
+append([], ?l, ?l).
+append([?h|?t], ?l, [?h|?r]) :- append(?t, ?l, ?r).
+
+member(?x, [?x|?t]).
+member(?x, [?h|?t]) :- member(?x, ?t).
+
+memberchk(?x, ?l) :- once(member(?x, ?l)).
+
+reverse(?l, ?r) :- '$reverse_acc'(?l, [], ?r).
+
+'$reverse_acc'([], ?r, ?r).
+'$reverse_acc'([?h|?t], ?a, ?r) :- '$reverse_acc'(?t, [?h|?a], ?r).
+
+select(?x, [?x|?t], ?t).
+select(?x, [?h|?t], [?h|?r]) :- select(?x, ?t, ?r).
+
+last([?x], ?x).
+last([?h|?t], ?x) :- last(?t, ?x).
+
+permutation([], []).
+permutation(?l, [?h|?t]) :- select(?h, ?l, ?r), permutation(?r, ?t).
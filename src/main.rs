@@ -1,27 +1,119 @@
-use std::collections::HashMap;
+use prolog_interpreter::{
+    interpreter::DB,
+    lexer,
+    operators::Operators,
+    parser::{Item, Parser, Syntax, Term},
+};
 
-use parser::Value;
+fn load(source: &str, program: String) -> (Vec<Item>, Operators, Syntax) {
+    let parsed = lexer::tokenize(program).and_then(|tokens| {
+        let mut parser = Parser::new(tokens);
+        Ok((parser.parse()?, parser.operators().clone(), parser.syntax()))
+    });
+    match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => exit(&format!("{}: {}", source, err)),
+    }
+}
 
-mod interpreter;
-mod lexer;
-mod parser;
+fn read(source: &str) -> Vec<u8> {
+    match std::fs::read(source) {
+        Ok(bytes) => bytes,
+        Err(err) => exit(&format!("can't read {}: {}", source, err)),
+    }
+}
 
-fn query() -> Value {
-    let tokens = lexer::tokenize("pos(?y).".to_string());
+fn text(source: &str, bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(program) => program,
+        Err(err) => exit(&format!("can't read {}: {}", source, err)),
+    }
+}
 
-    parser::Parser::new(tokens).parse().first().unwrap().clone()
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
 
-fn main() {
-    let program = "pos(?x) :- ?x <= 3.".to_string();
+fn compile(source: &str, output: &str) {
+    let (program, _, _) = load(source, text(source, read(source)));
+    let db = DB::new(program);
+    if let Err(err) = db.save(output) {
+        exit(&format!("can't write {}: {}", output, err));
+    }
+}
 
-    let tokens = lexer::tokenize(program);
-    // println!("{:?}", tokens);
+fn run(source: &str, queries: &[String]) {
+    let bytes = read(source);
+    let (db, operators, syntax) = if bytes.starts_with(b"PLDB") {
+        match DB::load(source) {
+            Ok(db) => (db, Operators::default(), Syntax::default()),
+            Err(err) => exit(&format!("can't load {}: {}", source, err)),
+        }
+    } else {
+        let (program, operators, syntax) = load(source, text(source, bytes));
+        let db = DB::consult(program, |db, query| {
+            print_answers(db, query, &operators, syntax);
+        });
+        (db, operators, syntax)
+    };
 
-    let facts = parser::Parser::new(tokens).parse();
-    // println!("{:?}", facts);
+    for query in queries {
+        let parsed = lexer::tokenize(query.clone()).and_then(|tokens| {
+            Parser::new(tokens)
+                .with_syntax(syntax)
+                .with_operators(operators.clone())
+                .parse_query()
+        });
+        match parsed {
+            Ok(query) => print_answers(&db, &query, &operators, syntax),
+            Err(err) => eprintln!("Warning: can't parse query {}: {}", query, err),
+        }
+    }
+}
+
+fn print_answers(db: &DB, query: &Term, operators: &Operators, syntax: Syntax) {
+    println!("?- {}.", query.display(operators).with_syntax(syntax));
+    let mut names = vec![];
+    query.collect_vars(&mut names);
+    names.retain(|name| !name.starts_with('$'));
 
-    let db = interpreter::DB::new(facts);
+    let answers = match db.query_all(query) {
+        Ok(answers) => answers,
+        Err(err) => {
+            eprintln!("Warning: query raised {}", err);
+            return;
+        }
+    };
+    if answers.is_empty() {
+        println!("false.");
+    }
+    for answer in answers {
+        let bindings: Vec<String> = names
+            .iter()
+            .filter(|name| answer[*name] != Term::Variable(name.to_string()))
+            .map(|name| {
+                let binding = vec![Term::Variable(name.clone()), answer[name].clone()];
+                let binding = Term::CompoundTerm("=".to_string(), binding);
+                binding.display(operators).with_syntax(syntax).to_string()
+            })
+            .collect();
+        if bindings.is_empty() {
+            println!("true.");
+        } else {
+            println!("{}.", bindings.join(", "));
+        }
+    }
+}
 
-    println!("{:?}", db.query(&query(), &mut HashMap::new()));
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [command, source, output] if command == "compile" => compile(source, output),
+        [source, queries @ ..] => run(source, queries),
+        _ => {
+            eprintln!("usage: prolog-interpreter <file> [query...] | compile <source> <output>");
+            std::process::exit(2);
+        }
+    }
 }
@@ -0,0 +1,145 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Specifier {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+    Xf,
+    Yf,
+}
+
+impl Specifier {
+    pub fn from_name(name: &str) -> Option<Specifier> {
+        match name {
+            "xfx" => Some(Specifier::Xfx),
+            "xfy" => Some(Specifier::Xfy),
+            "yfx" => Some(Specifier::Yfx),
+            "fy" => Some(Specifier::Fy),
+            "fx" => Some(Specifier::Fx),
+            "xf" => Some(Specifier::Xf),
+            "yf" => Some(Specifier::Yf),
+            _ => None,
+        }
+    }
+}
+
+const DEFAULTS: &[(usize, Specifier, &str)] = &[
+    (1200, Specifier::Xfx, ":-"),
+    (1200, Specifier::Xfx, "-->"),
+    (1200, Specifier::Fx, ":-"),
+    (1200, Specifier::Fx, "?-"),
+    (1150, Specifier::Fx, "dynamic"),
+    (1150, Specifier::Fx, "table"),
+    (1150, Specifier::Fx, "persistent"),
+    (1150, Specifier::Fx, "initialization"),
+    (1100, Specifier::Xfy, ";"),
+    (1050, Specifier::Xfy, "->"),
+    (1000, Specifier::Xfy, ","),
+    (900, Specifier::Fy, "\\+"),
+    (900, Specifier::Fy, "not"),
+    (700, Specifier::Xfx, "="),
+    (700, Specifier::Xfx, "\\="),
+    (700, Specifier::Xfx, "=.."),
+    (700, Specifier::Xfx, "=="),
+    (700, Specifier::Xfx, "\\=="),
+    (700, Specifier::Xfx, "@<"),
+    (700, Specifier::Xfx, "@>"),
+    (700, Specifier::Xfx, "@=<"),
+    (700, Specifier::Xfx, "@>="),
+    (700, Specifier::Xfx, "is"),
+    (700, Specifier::Xfx, "=:="),
+    (700, Specifier::Xfx, "=\\="),
+    (700, Specifier::Xfx, ">"),
+    (700, Specifier::Xfx, "<"),
+    (700, Specifier::Xfx, ">="),
+    (700, Specifier::Xfx, "=<"),
+    (700, Specifier::Xfx, "<="),
+    (500, Specifier::Yfx, "+"),
+    (500, Specifier::Yfx, "-"),
+    (500, Specifier::Yfx, "/\\"),
+    (500, Specifier::Yfx, "\\/"),
+    (400, Specifier::Yfx, "*"),
+    (400, Specifier::Yfx, "/"),
+    (400, Specifier::Yfx, "//"),
+    (400, Specifier::Yfx, "rem"),
+    (400, Specifier::Yfx, "mod"),
+    (400, Specifier::Yfx, "<<"),
+    (400, Specifier::Yfx, ">>"),
+    (200, Specifier::Xfx, "**"),
+    (200, Specifier::Xfy, "^"),
+    (200, Specifier::Xfy, ":"),
+    (200, Specifier::Fy, "-"),
+    (200, Specifier::Fy, "\\"),
+];
+
+#[derive(Debug, Clone)]
+pub struct Operators {
+    prefix: HashMap<String, (usize, Specifier)>,
+    infix: HashMap<String, (usize, Specifier)>,
+    postfix: HashMap<String, (usize, Specifier)>,
+}
+
+impl Default for Operators {
+    fn default() -> Operators {
+        let mut operators = Operators {
+            prefix: HashMap::new(),
+            infix: HashMap::new(),
+            postfix: HashMap::new(),
+        };
+        for (priority, specifier, name) in DEFAULTS {
+            operators.add(*priority, *specifier, name);
+        }
+        operators
+    }
+}
+
+impl Operators {
+    pub fn defaults() -> &'static Operators {
+        static DEFAULT: OnceLock<Operators> = OnceLock::new();
+        DEFAULT.get_or_init(Operators::default)
+    }
+
+    pub fn add(&mut self, priority: usize, specifier: Specifier, name: &str) {
+        let table = match specifier {
+            Specifier::Fy | Specifier::Fx => &mut self.prefix,
+            Specifier::Xfx | Specifier::Xfy | Specifier::Yfx => &mut self.infix,
+            Specifier::Xf | Specifier::Yf => &mut self.postfix,
+        };
+        if priority == 0 {
+            table.remove(name);
+        } else {
+            table.insert(name.to_string(), (priority, specifier));
+        }
+    }
+
+    pub fn prefix(&self, name: &str) -> Option<(usize, usize)> {
+        self.prefix
+            .get(name)
+            .map(|(priority, specifier)| match specifier {
+                Specifier::Fy => (*priority, *priority),
+                _ => (*priority, priority - 1),
+            })
+    }
+
+    pub fn infix(&self, name: &str) -> Option<(usize, usize, usize)> {
+        self.infix
+            .get(name)
+            .map(|(priority, specifier)| match specifier {
+                Specifier::Xfy => (*priority, priority - 1, *priority),
+                Specifier::Yfx => (*priority, *priority, priority - 1),
+                _ => (*priority, priority - 1, priority - 1),
+            })
+    }
+
+    pub fn postfix(&self, name: &str) -> Option<(usize, usize)> {
+        self.postfix
+            .get(name)
+            .map(|(priority, specifier)| match specifier {
+                Specifier::Yf => (*priority, *priority),
+                _ => (*priority, priority - 1),
+            })
+    }
+}
@@ -1,29 +1,316 @@
-use crate::lexer::Token;
+use std::fmt;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    CompoundTerm(String, Vec<Value>),
-    Predicate(String, Vec<Value>, Box<Value>),
-    List(Vec<Value>),
+use crate::{
+    error::Error,
+    lexer::Token,
+    operators::{Operators, Specifier},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    CompoundTerm(String, Vec<Term>),
+    List(Vec<Term>),
+    Cons(Box<Term>, Box<Term>),
     Str(String),
     Int(usize),
     Variable(String),
-    Eq(Box<Value>, Box<Value>),
-    And(Box<Value>, Box<Value>),
-    GreaterThan(Box<Value>, Box<Value>),
-    LessThan(Box<Value>, Box<Value>),
-    GreaterThanEqual(Box<Value>, Box<Value>),
-    LessThanEqual(Box<Value>, Box<Value>),
+}
+
+impl Term {
+    pub fn display<'a>(&'a self, operators: &'a Operators) -> TermDisplay<'a> {
+        TermDisplay {
+            term: self,
+            operators,
+            syntax: Syntax::Classic,
+        }
+    }
+
+    pub fn collect_vars(&self, out: &mut Vec<String>) {
+        match self {
+            Term::Variable(name) => {
+                if !out.contains(name) {
+                    out.push(name.clone());
+                }
+            }
+            Term::CompoundTerm(_, values) | Term::List(values) => {
+                values.iter().for_each(|val| val.collect_vars(out))
+            }
+            Term::Cons(left, right) => {
+                left.collect_vars(out);
+                right.collect_vars(out);
+            }
+            Term::Str(_) | Term::Int(_) => (),
+        }
+    }
+}
+
+fn priority(term: &Term, operators: &Operators) -> Option<usize> {
+    let Term::CompoundTerm(name, args) = term else {
+        return None;
+    };
+    match args.as_slice() {
+        [_, _] => operators.infix(name).map(|(priority, _, _)| priority),
+        [arg] if !is_operator_atom(arg, operators) => operators
+            .prefix(name)
+            .or_else(|| operators.postfix(name))
+            .map(|(priority, _)| priority),
+        _ => None,
+    }
+}
+
+fn is_operator_atom(term: &Term, operators: &Operators) -> bool {
+    match term {
+        Term::CompoundTerm(name, args) if args.is_empty() => {
+            operators.prefix(name).is_some()
+                || operators.infix(name).is_some()
+                || operators.postfix(name).is_some()
+        }
+        _ => false,
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, text: &str, quote: char) -> fmt::Result {
+    write!(f, "{}", quote)?;
+    for c in text.chars() {
+        match c {
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\\' => write!(f, "\\\\")?,
+            c if c == quote => write!(f, "\\{}", c)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "{}", quote)
+}
+
+fn write_atom(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    let symbolic = "+-*/\\^<>=~:.?@#&$";
+    let plain = name.starts_with(|c: char| c.is_lowercase())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain
+        || (!name.is_empty() && name.chars().all(|c| symbolic.contains(c)))
+        || name == "!"
+        || name == ";"
+    {
+        write!(f, "{}", name)
+    } else {
+        write_quoted(f, name, '\'')
+    }
+}
+
+pub struct TermDisplay<'a> {
+    term: &'a Term,
+    operators: &'a Operators,
+    syntax: Syntax,
+}
+
+impl TermDisplay<'_> {
+    pub fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    fn write_term(&self, f: &mut fmt::Formatter<'_>, term: &Term, max: usize) -> fmt::Result {
+        let display = term.display(self.operators).with_syntax(self.syntax);
+        match priority(term, self.operators) {
+            Some(priority) if priority > max => write!(f, "({})", display),
+            _ => write!(f, "{}", display),
+        }
+    }
+
+    fn write_args(&self, f: &mut fmt::Formatter<'_>, args: &[Term]) -> fmt::Result {
+        for (idx, arg) in args.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            self.write_term(f, arg, 999)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TermDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.term {
+            Term::Variable(name) if name.starts_with("$_") => write!(f, "_"),
+            Term::Variable(name) => match self.syntax {
+                Syntax::Iso
+                    if name.starts_with(|c: char| c.is_uppercase() || c == '_')
+                        && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                {
+                    write!(f, "{}", name)
+                }
+                Syntax::Iso => {
+                    let name: String = name.chars().filter(|c| c.is_alphanumeric()).collect();
+                    write!(f, "_{}", name)
+                }
+                Syntax::Classic => write!(f, "?{}", name),
+            },
+            Term::Int(val) => write!(f, "{}", val),
+            Term::Str(val) => write_quoted(f, val, '"'),
+            Term::List(values) => {
+                write!(f, "[")?;
+                self.write_args(f, values)?;
+                write!(f, "]")
+            }
+            Term::Cons(head, tail) => {
+                write!(f, "[")?;
+                self.write_term(f, head, 999)?;
+                let mut tail = tail.as_ref();
+                while let Term::Cons(head, rest) = tail {
+                    write!(f, ", ")?;
+                    self.write_term(f, head, 999)?;
+                    tail = rest;
+                }
+                match tail {
+                    Term::List(values) if values.is_empty() => write!(f, "]"),
+                    Term::List(values) => {
+                        write!(f, ", ")?;
+                        self.write_args(f, values)?;
+                        write!(f, "]")
+                    }
+                    tail => {
+                        write!(f, "|")?;
+                        self.write_term(f, tail, 999)?;
+                        write!(f, "]")
+                    }
+                }
+            }
+            Term::CompoundTerm(name, args) => {
+                let operators = self.operators;
+                if let ([left, right], Some((_, left_max, right_max))) =
+                    (args.as_slice(), operators.infix(name))
+                {
+                    self.write_term(f, left, left_max)?;
+                    match name.as_str() {
+                        "," => write!(f, ", ")?,
+                        name => {
+                            write!(f, " ")?;
+                            write_atom(f, name)?;
+                            write!(f, " ")?;
+                        }
+                    }
+                    return self.write_term(f, right, right_max);
+                }
+                if let [arg] = args.as_slice() {
+                    if !is_operator_atom(arg, operators) {
+                        if let Some((_, arg_max)) = operators.prefix(name) {
+                            write_atom(f, name)?;
+                            write!(f, " ")?;
+                            return self.write_term(f, arg, arg_max);
+                        }
+                        if let Some((_, arg_max)) = operators.postfix(name) {
+                            self.write_term(f, arg, arg_max)?;
+                            write!(f, " ")?;
+                            return write_atom(f, name);
+                        }
+                    }
+                }
+                write_atom(f, name)?;
+                if args.is_empty() {
+                    return Ok(());
+                }
+                write!(f, "(")?;
+                self.write_args(f, args)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(Operators::defaults()).fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Clause {
+    pub head: Term,
+    pub body: Term,
+}
+
+impl Clause {
+    pub fn fact(head: Term) -> Clause {
+        Clause {
+            head,
+            body: Term::CompoundTerm("true".to_string(), vec![]),
+        }
+    }
+
+    pub fn from_term(term: Term) -> Clause {
+        match term {
+            Term::CompoundTerm(name, mut args) if name == ":-" && args.len() == 2 => {
+                let body = args.pop().unwrap();
+                let head = args.pop().unwrap();
+                Clause { head, body }
+            }
+            head => Clause::fact(head),
+        }
+    }
+
+    pub fn is_fact(&self) -> bool {
+        matches!(&self.body, Term::CompoundTerm(name, args) if name == "true" && args.is_empty())
+    }
+
+    pub fn to_term(&self) -> Term {
+        if self.is_fact() {
+            self.head.clone()
+        } else {
+            Term::CompoundTerm(":-".to_string(), vec![self.head.clone(), self.body.clone()])
+        }
+    }
+
+    pub fn key(&self) -> Result<(String, usize), Error> {
+        match &self.head {
+            Term::CompoundTerm(name, args) => Ok((name.clone(), args.len())),
+            Term::Variable(_) => Err(Error::Instantiation),
+            head => Err(Error::Type("callable".to_string(), head.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Clause(Clause),
+    Directive(Term),
+    Query(Term),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Syntax {
+    #[default]
+    Classic,
+    Iso,
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     idx: usize,
+    syntax: Syntax,
+    operators: Operators,
+    anonymous: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, idx: 0 }
+        Parser {
+            tokens,
+            idx: 0,
+            syntax: Syntax::default(),
+            operators: Operators::default(),
+            anonymous: 0,
+        }
+    }
+
+    pub fn with_syntax(mut self, syntax: Syntax) -> Parser {
+        self.syntax = syntax;
+        self
+    }
+
+    pub fn with_operators(mut self, operators: Operators) -> Parser {
+        self.operators = operators;
+        self
     }
 
     fn scan<T>(&self, cond: fn(&Token) -> Option<T>) -> bool {
@@ -34,156 +321,477 @@ impl Parser {
         self.tokens.get(self.idx + offset).and_then(cond).is_some()
     }
 
-    fn consume<T>(&mut self, cond: fn(&Token) -> Option<T>) -> T {
+    fn consume<T>(&mut self, cond: fn(&Token) -> Option<T>) -> Result<T, Error> {
         match self.tokens.get(self.idx).and_then(cond) {
             Some(val) => {
                 self.idx += 1;
-                val
-            }
-            None => {
-                panic!("couldn't find token {:?}", self.tokens.get(self.idx..))
+                Ok(val)
             }
+            None => Err(self.unexpected()),
+        }
+    }
+
+    fn unexpected(&self) -> Error {
+        match self.tokens.get(self.idx) {
+            Some(token) => Error::Syntax(format!("unexpected {:?}", token)),
+            None => Error::Syntax("unexpected end of input".to_string()),
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Value> {
+    pub fn operators(&self) -> &Operators {
+        &self.operators
+    }
+
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<Item>, Error> {
         let mut ast = vec![];
 
         while self.idx < self.tokens.len() {
-            ast.push(self.parse_expr());
-            self.consume(|t| t.as_dot());
+            match self.parse_expr(1200)? {
+                Term::CompoundTerm(name, mut args) if name == ":-" && args.len() == 1 => {
+                    let directive = args.pop().unwrap();
+                    if !self.syntax_directive(&directive)? && !self.op_directive(&directive)? {
+                        ast.push(Item::Directive(directive));
+                    }
+                }
+                Term::CompoundTerm(name, mut args) if name == "?-" && args.len() == 1 => {
+                    ast.push(Item::Query(args.pop().unwrap()));
+                }
+                term => {
+                    let clause = Clause::from_term(term);
+                    if clause.key().is_err() {
+                        return Err(Error::Syntax(format!(
+                            "clause head must be callable, found {}",
+                            clause.head
+                        )));
+                    }
+                    ast.push(Item::Clause(clause));
+                }
+            }
+            self.consume(|t| t.as_dot())?;
+        }
+
+        Ok(ast)
+    }
+
+    pub fn parse_query(&mut self) -> Result<Term, Error> {
+        let goal = self.parse_expr(1200)?;
+        if self.scan(|t| t.as_dot()) {
+            self.consume(|t| t.as_dot())?;
+        }
+        if self.idx < self.tokens.len() {
+            return Err(self.unexpected());
+        }
+        Ok(goal)
+    }
+
+    fn syntax_directive(&mut self, directive: &Term) -> Result<bool, Error> {
+        match directive {
+            Term::CompoundTerm(name, args) if name == "syntax" && args.len() == 1 => {
+                self.syntax = match &args[0] {
+                    Term::CompoundTerm(syntax, none) if syntax == "iso" && none.is_empty() => {
+                        Syntax::Iso
+                    }
+                    Term::CompoundTerm(syntax, none) if syntax == "classic" && none.is_empty() => {
+                        Syntax::Classic
+                    }
+                    syntax => return Err(Error::Domain("syntax".to_string(), syntax.clone())),
+                };
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn op_directive(&mut self, directive: &Term) -> Result<bool, Error> {
+        let Term::CompoundTerm(name, args) = directive else {
+            return Ok(false);
+        };
+        let [priority, specifier, names] = args.as_slice() else {
+            return Ok(false);
+        };
+        if name != "op" {
+            return Ok(false);
+        }
+        let priority = match priority {
+            Term::Int(priority) if *priority <= 1200 => *priority,
+            priority => {
+                return Err(Error::Domain(
+                    "operator_priority".to_string(),
+                    priority.clone(),
+                ))
+            }
+        };
+        let specifier = match specifier {
+            Term::CompoundTerm(name, none) if none.is_empty() => Specifier::from_name(name),
+            _ => None,
+        }
+        .ok_or_else(|| Error::Domain("operator_specifier".to_string(), specifier.clone()))?;
+        let names = match names {
+            Term::List(names) => names.as_slice(),
+            name => std::slice::from_ref(name),
+        };
+        for name in names {
+            match name {
+                Term::CompoundTerm(name, none) if none.is_empty() && name != "," => {
+                    self.operators.add(priority, specifier, name)
+                }
+                Term::CompoundTerm(name, none) if none.is_empty() => {
+                    return Err(Error::Syntax(format!("can't modify operator '{}'", name)))
+                }
+                name => return Err(Error::Type("atom".to_string(), name.clone())),
+            }
+        }
+        Ok(true)
+    }
+
+    fn operator(&self, offset: usize) -> Option<String> {
+        match self.tokens.get(self.idx + offset)? {
+            Token::Id(name) => Some(name.clone()),
+            Token::Comma => Some(",".to_string()),
+            _ => None,
+        }
+    }
+
+    fn starts_term(&self, offset: usize) -> bool {
+        match self.tokens.get(self.idx + offset) {
+            None
+            | Some(
+                Token::Dot | Token::Comma | Token::CloseParen | Token::CloseSqBrace | Token::Bar,
+            ) => false,
+            Some(Token::Id(name)) => {
+                self.operators.prefix(name).is_some()
+                    || self.scan_ahead(offset + 1, |t| t.as_functor_open_paren())
+                    || (self.operators.infix(name).is_none()
+                        && self.operators.postfix(name).is_none())
+            }
+            Some(_) => true,
+        }
+    }
+
+    fn parse_expr(&mut self, max: usize) -> Result<Term, Error> {
+        let (mut left, mut priority) = self.parse_prefix(max)?;
+
+        while let Some(name) = self.operator(0) {
+            if let Some((op, left_max, right_max)) = self.operators.infix(&name) {
+                if op <= max && priority <= left_max && self.starts_term(1) {
+                    self.idx += 1;
+                    let right = self.parse_expr(right_max)?;
+                    left = Term::CompoundTerm(name, vec![left, right]);
+                    priority = op;
+                    continue;
+                }
+            }
+            match self.operators.postfix(&name) {
+                Some((op, arg_max)) if op <= max && priority <= arg_max => {
+                    self.idx += 1;
+                    left = Term::CompoundTerm(name, vec![left]);
+                    priority = op;
+                }
+                _ => break,
+            }
         }
+        Ok(left)
+    }
 
-        ast
+    fn parse_prefix(&mut self, max: usize) -> Result<(Term, usize), Error> {
+        if let Some(Token::Id(name)) = self.tokens.get(self.idx) {
+            if let Some((op, arg_max)) = self.operators.prefix(name) {
+                if !self.scan_ahead(1, |t| t.as_functor_open_paren()) && self.starts_term(1) {
+                    let name = name.clone();
+                    self.idx += 1;
+                    let arg = self.parse_expr(arg_max.min(max))?;
+                    return Ok((Term::CompoundTerm(name, vec![arg]), op.min(max)));
+                }
+            }
+        }
+        Ok((self.parse_primary()?, 0))
     }
 
-    fn parse_single_expr(&mut self) -> Value {
-        if self.scan(|t| t.as_id()) && self.scan_ahead(1, |t| t.as_open_paren()) {
-            self.parse_fact_or_predicate()
+    fn parse_primary(&mut self) -> Result<Term, Error> {
+        if self.syntax == Syntax::Iso && self.scan(|t| t.as_capitalized_id()) {
+            let name = self.consume(|t| t.as_capitalized_id())?;
+            Ok(Term::Variable(name))
+        } else if self.scan(|t| t.as_id()) && self.scan_ahead(1, |t| t.as_functor_open_paren()) {
+            self.parse_compound()
+        } else if self.scan(|t| t.as_id()) {
+            match self.consume(|t| t.as_id())? {
+                name if name == "[]" => Ok(Term::List(vec![])),
+                name => Ok(Term::CompoundTerm(name, vec![])),
+            }
         } else if self.scan(|t| t.as_str()) {
-            self.parse_str()
+            Ok(Term::Str(self.consume(|t| t.as_str())?))
         } else if self.scan(|t| t.as_int()) {
-            self.parse_int()
+            Ok(Term::Int(self.consume(|t| t.as_int())?))
         } else if self.scan(|t| t.as_variable()) {
-            self.parse_variable()
+            Ok(Term::Variable(self.consume(|t| t.as_variable())?))
         } else if self.scan(|t| t.as_open_square_brace()) {
             self.parse_list()
         } else if self.scan(|t| t.as_underscore()) {
-            self.parse_underscore()
+            self.consume(|t| t.as_underscore())?;
+            self.anonymous += 1;
+            Ok(Term::Variable(format!("$_{}", self.anonymous)))
+        } else if self.scan(|t| t.as_open_paren().or(t.as_functor_open_paren())) {
+            self.consume(|t| t.as_open_paren().or(t.as_functor_open_paren()))?;
+            let expr = self.parse_expr(1200)?;
+            self.consume(|t| t.as_close_paren())?;
+            Ok(expr)
         } else {
-            panic!("no expr found {:?}", self.tokens.get(self.idx..))
+            Err(self.unexpected())
         }
     }
 
-    fn parse_expr(&mut self) -> Value {
-        let expr = self.parse_single_expr();
+    fn parse_list(&mut self) -> Result<Term, Error> {
+        self.consume(|t| t.as_open_square_brace())?;
+        let mut values: Vec<Term> = vec![];
+        while !self.scan(|t| t.as_close_square_brace()) && !self.scan(|t| t.as_bar()) {
+            values.push(self.parse_expr(999)?);
 
-        if self.scan(|t| t.as_eq()) {
-            self.parse_eq(expr)
-        } else if self.scan(|t| t.as_comma()) {
-            self.parse_and(expr)
-        } else if self.scan(|t| t.as_greater_than()) {
-            self.parse_greater_than(expr)
-        } else if self.scan(|t| t.as_less_than()) {
-            self.parse_less_than(expr)
-        } else if self.scan(|t| t.as_greater_than_equal()) {
-            self.parse_greater_than_equal(expr)
-        } else if self.scan(|t| t.as_less_than_equal()) {
-            self.parse_less_than_equal(expr)
+            if !self.scan(|t| t.as_close_square_brace()) && !self.scan(|t| t.as_bar()) {
+                self.consume(|t| t.as_comma())?;
+            }
+        }
+
+        if self.scan(|t| t.as_bar()) {
+            self.consume(|t| t.as_bar())?;
+            let tail = self.parse_expr(999)?;
+            self.consume(|t| t.as_close_square_brace())?;
+            Ok(values.into_iter().rev().fold(tail, |tail, head| {
+                Term::Cons(Box::new(head), Box::new(tail))
+            }))
         } else {
-            expr
+            self.consume(|t| t.as_close_square_brace())?;
+            Ok(Term::List(values))
         }
     }
 
-    fn parse_less_than_equal(&mut self, left: Value) -> Value {
-        self.consume(|t| t.as_less_than_equal());
-        let right = self.parse_single_expr();
-        Value::LessThanEqual(Box::new(left), Box::new(right))
-    }
+    fn parse_compound(&mut self) -> Result<Term, Error> {
+        let name = self.consume(|t| t.as_id())?;
+        self.consume(|t| t.as_functor_open_paren())?;
+        let mut args: Vec<Term> = vec![];
 
-    fn parse_greater_than_equal(&mut self, left: Value) -> Value {
-        self.consume(|t| t.as_greater_than_equal());
-        let right = self.parse_single_expr();
-        Value::GreaterThanEqual(Box::new(left), Box::new(right))
-    }
+        while !self.scan(|t| t.as_close_paren()) {
+            args.push(self.parse_expr(999)?);
+            if !self.scan(|t| t.as_close_paren()) {
+                self.consume(|t| t.as_comma())?;
+            }
+        }
 
-    fn parse_less_than(&mut self, left: Value) -> Value {
-        self.consume(|t| t.as_less_than());
-        let right = self.parse_single_expr();
-        Value::LessThan(Box::new(left), Box::new(right))
+        self.consume(|t| t.as_close_paren())?;
+        Ok(Term::CompoundTerm(name, args))
     }
+}
 
-    fn parse_greater_than(&mut self, left: Value) -> Value {
-        self.consume(|t| t.as_greater_than());
-        let right = self.parse_single_expr();
-        Value::GreaterThan(Box::new(left), Box::new(right))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn parser(source: &str) -> Parser {
+        Parser::new(lexer::tokenize(source.to_string()).unwrap())
     }
 
-    fn parse_and(&mut self, left: Value) -> Value {
-        self.consume(|t| t.as_comma());
-        let right = self.parse_single_expr();
-        Value::And(Box::new(left), Box::new(right))
+    fn term(source: &str) -> Term {
+        parser(source).parse_query().unwrap()
     }
 
-    fn parse_eq(&mut self, left: Value) -> Value {
-        self.consume(|t| t.as_eq());
-        let right = self.parse_single_expr();
-        Value::Eq(Box::new(left), Box::new(right))
+    fn atom(name: &str) -> Term {
+        Term::CompoundTerm(name.to_string(), vec![])
     }
 
-    fn parse_underscore(&mut self) -> Value {
-        self.consume(|t| t.as_underscore());
-        panic!("");
+    fn op(name: &str, left: Term, right: Term) -> Term {
+        Term::CompoundTerm(name.to_string(), vec![left, right])
     }
 
-    fn parse_list(&mut self) -> Value {
-        self.consume(|t| t.as_open_square_brace());
-        let mut values: Vec<Value> = vec![];
-        while !self.scan(|t| t.as_close_square_brace()) {
-            values.push(self.parse_expr());
+    #[test]
+    fn iso_variables() {
+        let mut parser =
+            parser(":- syntax(iso). p(X, _Y, x, _) :- q(X). :- syntax(classic). r(X).");
+        let items = parser.parse().unwrap();
+        let Item::Clause(clause) = &items[0] else {
+            panic!("expected a clause, found {:?}", items[0]);
+        };
+        let Term::CompoundTerm(_, args) = &clause.head else {
+            unreachable!()
+        };
+        assert_eq!(args[0], Term::Variable("X".to_string()));
+        assert_eq!(args[1], Term::Variable("_Y".to_string()));
+        assert_eq!(args[2], atom("x"));
+        assert!(matches!(&args[3], Term::Variable(name) if name.starts_with("$_")));
+        let Item::Clause(clause) = &items[1] else {
+            panic!("expected a clause, found {:?}", items[1]);
+        };
+        assert_eq!(
+            clause.head,
+            Term::CompoundTerm("r".to_string(), vec![atom("X")])
+        );
+        assert_eq!(parser.syntax(), Syntax::Classic);
 
-            if !self.scan(|t| t.as_close_square_brace()) {
-                self.consume(|t| t.as_comma());
-            }
-        }
-        self.consume(|t| t.as_close_square_brace());
-        Value::List(values)
+        let term = Parser::new(lexer::tokenize("f(X, ?y)".to_string()).unwrap())
+            .with_syntax(Syntax::Iso)
+            .parse_query()
+            .unwrap();
+        assert_eq!(term.display(Operators::defaults()).to_string(), "f(?X, ?y)");
+        assert_eq!(
+            term.display(Operators::defaults())
+                .with_syntax(Syntax::Iso)
+                .to_string(),
+            "f(X, _y)"
+        );
+        assert!(matches!(
+            self::parser(":- syntax(modern).").parse(),
+            Err(Error::Domain(domain, _)) if domain == "syntax"
+        ));
     }
 
-    fn parse_variable(&mut self) -> Value {
-        let name = self.consume(|t| t.as_variable());
-        Value::Variable(name)
-    }
+    #[test]
+    fn operator_priority_and_associativity() {
+        let (a, b, c) = (atom("a"), atom("b"), atom("c"));
+        assert_eq!(
+            term("a - b - c"),
+            op("-", op("-", a.clone(), b.clone()), c.clone())
+        );
+        assert_eq!(
+            term("a ^ b ^ c"),
+            op("^", a.clone(), op("^", b.clone(), c.clone()))
+        );
+        assert_eq!(
+            term("a + b * c"),
+            op("+", a.clone(), op("*", b.clone(), c.clone()))
+        );
+        assert_eq!(
+            term("- - a"),
+            Term::CompoundTerm(
+                "-".to_string(),
+                vec![Term::CompoundTerm("-".to_string(), vec![a.clone()])]
+            )
+        );
+        assert!(parser("a = b = c").parse_query().is_err());
+        assert!(parser("a :- b :- c").parse_query().is_err());
 
-    fn parse_str(&mut self) -> Value {
-        let val = self.consume(|t| t.as_str());
-        Value::Str(val)
-    }
+        let mut parser = parser(
+            "
+            :- op(700, xfx, ===>).
+            :- op(200, xfy, [++, +++]).
+            :- op(500, yfx, --).
+            :- op(100, fy, ~~).
+            rule(a ===> b ++ c ++ d -- e -- f, ~~ ~~ g).
+            :- op(0, xfx, ===>).
+            :- op(0, fy, ~~).
+        ",
+        );
+        let items = parser.parse().unwrap();
+        let Item::Clause(clause) = &items[0] else {
+            panic!("expected a clause, found {:?}", items[0]);
+        };
+        let right = op(
+            "--",
+            op(
+                "--",
+                op("++", b.clone(), op("++", c.clone(), atom("d"))),
+                atom("e"),
+            ),
+            atom("f"),
+        );
+        let prefix = |arg| Term::CompoundTerm("~~".to_string(), vec![arg]);
+        assert_eq!(
+            clause.head,
+            Term::CompoundTerm(
+                "rule".to_string(),
+                vec![op("===>", a.clone(), right), prefix(prefix(atom("g")))]
+            )
+        );
+        assert!(parser.operators().infix("===>").is_none());
+        assert!(parser.operators().prefix("~~").is_none());
+        assert_eq!(parser.operators().infix("+++"), Some((200, 199, 200)));
 
-    fn parse_int(&mut self) -> Value {
-        let val = self.consume(|t| t.as_int());
-        Value::Int(val)
+        let mut removed = self::parser(":- op(0, xfx, ===>). p(a ===> b).");
+        assert!(removed.parse().is_err());
+        assert!(matches!(
+            self::parser(":- op(1201, xfx, ===>).").parse(),
+            Err(Error::Domain(domain, _)) if domain == "operator_priority"
+        ));
+        assert!(matches!(
+            self::parser(":- op(700, xxx, ===>).").parse(),
+            Err(Error::Domain(domain, _)) if domain == "operator_specifier"
+        ));
+        assert!(matches!(
+            self::parser(":- op(700, xfx, ',').").parse(),
+            Err(Error::Syntax(_))
+        ));
     }
 
-    fn parse_fact_or_predicate(&mut self) -> Value {
-        let name = self.consume(|t| t.as_id());
-        self.consume(|t| t.as_open_paren());
-        let mut args: Vec<Value> = vec![];
+    #[test]
+    fn empty_list_atom() {
+        assert_eq!(term("'[]'"), Term::List(vec![]));
+        assert_eq!(term("[]"), Term::List(vec![]));
+        assert_eq!(
+            term("'[]'(a)"),
+            Term::CompoundTerm("[]".to_string(), vec![atom("a")])
+        );
+        assert_eq!(
+            term("[a|'[]']"),
+            Term::Cons(Box::new(atom("a")), Box::new(Term::List(vec![])))
+        );
+    }
 
-        while !self.scan(|t| t.as_close_paren()) {
-            args.push(self.parse_single_expr());
-            if !self.scan(|t| t.as_close_paren()) {
-                self.consume(|t| t.as_comma());
-            }
-        }
+    #[test]
+    fn clause_key() {
+        assert_eq!(
+            Clause::fact(term("p(a, b)")).key().unwrap(),
+            ("p".to_string(), 2)
+        );
+        assert!(matches!(
+            Clause::fact(Term::Variable("x".to_string())).key(),
+            Err(Error::Instantiation)
+        ));
+        assert!(matches!(
+            Clause::fact(Term::Int(1)).key(),
+            Err(Error::Type(expected, Term::Int(1))) if expected == "callable"
+        ));
+        assert!(matches!(
+            parser("1 :- true.").parse(),
+            Err(Error::Syntax(_))
+        ));
+        assert!(matches!(parser("\"s\".").parse(), Err(Error::Syntax(_))));
+    }
 
-        self.consume(|t| t.as_close_paren());
-        if self.scan(|t| t.as_back_arrow()) {
-            self.consume(|t| t.as_back_arrow());
-            let body = self.parse_expr();
-            Value::Predicate(name, args, Box::new(body))
-        } else {
-            Value::CompoundTerm(name, args)
+    #[test]
+    fn display_round_trips() {
+        let operators = Operators::defaults();
+        for source in [
+            "f(a, ?x, _)",
+            "a :- b, c ; d -> e",
+            "(a :- b) :- c",
+            "(a , b)",
+            "f((a, b), (c :- d))",
+            "1 - (2 - 3) - 4",
+            "(2 ** 3) ** 4",
+            "- (1)",
+            "- a",
+            "- (- a)",
+            "\\+ \\+ a",
+            "f(-)",
+            "- - - a",
+            "[1, 2|?t]",
+            "[a, [b, c], \"str\\n\"]",
+            "'hello world'('It''s', 'A', [])",
+            "'\\n'",
+            "f(;, '|', !, [])",
+            "a = \\+ b",
+            "(a = b) = c",
+            "x is 1 + 2 * (3 - 4) mod 5",
+            "f((a :- b), (c , d))",
+        ] {
+            let term = term(source);
+            let printed = term.display(operators).to_string();
+            assert_eq!(self::term(&printed), term, "{source} printed as {printed}");
         }
+        assert_eq!(term("a:-b,c;d->e").to_string(), "a :- b, c ; d -> e");
+        assert_eq!(term("1-(2-3)").to_string(), "1 - (2 - 3)");
+        assert_eq!(term("f((a,b))").to_string(), "f((a, b))");
     }
 }
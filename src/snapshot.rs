@@ -0,0 +1,157 @@
+use std::{collections::HashMap, io};
+
+use crate::{
+    journal::{self, crc32, write_u32, write_u64},
+    parser::Term,
+};
+
+const MAGIC: &[u8; 4] = b"PLDB";
+const VERSION: u32 = 3;
+
+#[derive(Default)]
+pub struct Writer {
+    atoms: Vec<String>,
+    ids: HashMap<String, u32>,
+    body: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        write_u32(&mut self.body, val);
+    }
+
+    pub fn atom(&mut self, name: &str) {
+        let id = match self.ids.get(name) {
+            Some(id) => *id,
+            None => {
+                let id = self.atoms.len() as u32;
+                self.atoms.push(name.to_string());
+                self.ids.insert(name.to_string(), id);
+                id
+            }
+        };
+        self.u32(id);
+    }
+
+    fn values(&mut self, values: &[Term]) {
+        self.u32(values.len() as u32);
+        for value in values {
+            self.value(value);
+        }
+    }
+
+    pub fn value(&mut self, value: &Term) {
+        match value {
+            Term::CompoundTerm(name, args) => {
+                self.body.push(0);
+                self.atom(name);
+                self.values(args);
+            }
+            Term::List(values) => {
+                self.body.push(2);
+                self.values(values);
+            }
+            Term::Cons(left, right) => {
+                self.body.push(3);
+                self.value(left);
+                self.value(right);
+            }
+            Term::Str(val) => {
+                self.body.push(4);
+                self.atom(val);
+            }
+            Term::Int(val) => {
+                self.body.push(5);
+                write_u64(&mut self.body, *val as u64);
+            }
+            Term::Variable(name) => {
+                self.body.push(6);
+                self.atom(name);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_u32(&mut out, VERSION);
+        write_u32(&mut out, self.atoms.len() as u32);
+        for atom in self.atoms.iter() {
+            journal::write_str(&mut out, atom);
+        }
+        out.extend(self.body);
+        let checksum = crc32(&out);
+        write_u32(&mut out, checksum);
+        out
+    }
+}
+
+pub struct Reader<'a> {
+    atoms: Vec<String>,
+    bytes: journal::Reader<'a>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> io::Result<Reader<'a>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 12 || &bytes[..4] != MAGIC {
+            return Err(invalid("not a database snapshot"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(body).to_le_bytes() != checksum {
+            return Err(invalid("corrupt database snapshot"));
+        }
+
+        let mut bytes = journal::Reader::new(&body[4..]);
+        let version = bytes.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+        let count = bytes.u32()?;
+        let atoms = (0..count).map(|_| bytes.str()).collect::<io::Result<_>>()?;
+        Ok(Reader { atoms, bytes })
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        self.bytes.u32()
+    }
+
+    pub fn atom(&mut self) -> io::Result<String> {
+        let id = self.u32()? as usize;
+        match self.atoms.get(id) {
+            Some(atom) => Ok(atom.clone()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown atom {}", id),
+            )),
+        }
+    }
+
+    fn values(&mut self) -> io::Result<Vec<Term>> {
+        let len = self.u32()?;
+        (0..len).map(|_| self.value()).collect()
+    }
+
+    pub fn value(&mut self) -> io::Result<Term> {
+        Ok(match self.bytes.u8()? {
+            0 => Term::CompoundTerm(self.atom()?, self.values()?),
+            2 => Term::List(self.values()?),
+            3 => Term::Cons(Box::new(self.value()?), Box::new(self.value()?)),
+            4 => Term::Str(self.atom()?),
+            5 => Term::Int(self.bytes.u64()? as usize),
+            6 => Term::Variable(self.atom()?),
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown value tag {}", tag),
+                ))
+            }
+        })
+    }
+}
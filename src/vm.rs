@@ -0,0 +1,697 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    compiler::{Builtin, CompiledQuery, Const, Functor, Instr, Program, Reg},
+    error::Error,
+    parser::{Item, Term},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Ref(usize),
+    Str(usize),
+    Fun(Functor),
+    Con(Const),
+    Lis(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Read,
+    Write,
+}
+
+struct Env {
+    prev: Option<Rc<Env>>,
+    cp: usize,
+    ys: RefCell<Vec<Cell>>,
+}
+
+struct ChoicePoint {
+    args: Vec<Cell>,
+    env: Option<Rc<Env>>,
+    cp: usize,
+    b0: usize,
+    alt: usize,
+    trail_len: usize,
+    heap_len: usize,
+}
+
+pub struct Machine {
+    program: Program,
+    heap: Vec<Cell>,
+    x: Vec<Cell>,
+    trail: Vec<usize>,
+    env: Option<Rc<Env>>,
+    choicepoints: Vec<ChoicePoint>,
+    p: usize,
+    cp: usize,
+    b0: usize,
+    s: usize,
+    mode: Mode,
+    num_args: usize,
+}
+
+impl Machine {
+    pub fn new(program: Vec<Item>) -> Result<Machine, Error> {
+        let clauses: Vec<_> = program
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Clause(clause) => Some(clause),
+                Item::Directive(_) | Item::Query(_) => None,
+            })
+            .collect();
+        let program = Program::compile(&clauses)?;
+        Ok(Machine {
+            program,
+            heap: vec![],
+            x: vec![],
+            trail: vec![],
+            env: None,
+            choicepoints: vec![],
+            p: 0,
+            cp: 0,
+            b0: 0,
+            s: 0,
+            mode: Mode::Read,
+            num_args: 0,
+        })
+    }
+
+    pub fn query(&mut self, query: &Term) -> Result<Option<HashMap<String, Term>>, Error> {
+        let mut answer = None;
+        self.run_query(query, |vars| {
+            answer = Some(vars);
+            false
+        })?;
+        Ok(answer)
+    }
+
+    pub fn query_all(&mut self, query: &Term) -> Result<Vec<HashMap<String, Term>>, Error> {
+        let mut answers = vec![];
+        self.run_query(query, |vars| {
+            answers.push(vars);
+            true
+        })?;
+        Ok(answers)
+    }
+
+    fn run_query<F>(&mut self, query: &Term, mut on_answer: F) -> Result<(), Error>
+    where
+        F: FnMut(HashMap<String, Term>) -> bool,
+    {
+        let compiled = self.program.compile_query(query)?;
+        self.reset(&compiled);
+
+        while self.run() {
+            let env = self.env.clone().unwrap();
+            let vars = compiled
+                .vars
+                .iter()
+                .map(|(name, idx)| (name.clone(), self.read(env.ys.borrow()[*idx])))
+                .collect();
+            if !on_answer(vars) || !self.backtrack() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, query: &CompiledQuery) {
+        self.heap.clear();
+        self.trail.clear();
+        self.choicepoints.clear();
+        self.x = vec![Cell::Ref(0); self.program.num_registers];
+        self.env = None;
+        self.p = query.start;
+        self.cp = query.start;
+        self.b0 = 0;
+    }
+
+    fn run(&mut self) -> bool {
+        loop {
+            let instr = self.program.code[self.p].clone();
+            self.p += 1;
+
+            let ok = match instr {
+                Instr::PutVariable(reg, idx) => {
+                    let cell = self.new_var();
+                    self.set(reg, cell);
+                    self.x[idx] = cell;
+                    true
+                }
+                Instr::PutValue(reg, idx) => {
+                    self.x[idx] = self.get(reg);
+                    true
+                }
+                Instr::PutStructure(functor, idx) => {
+                    self.x[idx] = Cell::Str(self.heap.len());
+                    self.heap.push(Cell::Fun(functor));
+                    true
+                }
+                Instr::PutList(idx) => {
+                    self.x[idx] = Cell::Lis(self.heap.len());
+                    true
+                }
+                Instr::PutConstant(constant, idx) => {
+                    self.x[idx] = Cell::Con(constant);
+                    true
+                }
+                Instr::GetVariable(reg, idx) => {
+                    self.set(reg, self.x[idx]);
+                    true
+                }
+                Instr::GetValue(reg, idx) => self.unify(self.get(reg), self.x[idx]),
+                Instr::GetStructure(functor, idx) => match self.deref(self.x[idx]) {
+                    Cell::Ref(addr) => {
+                        let str = Cell::Str(self.heap.len());
+                        self.heap.push(Cell::Fun(functor));
+                        self.bind(addr, str);
+                        self.mode = Mode::Write;
+                        true
+                    }
+                    Cell::Str(addr) if self.heap[addr] == Cell::Fun(functor) => {
+                        self.s = addr + 1;
+                        self.mode = Mode::Read;
+                        true
+                    }
+                    _ => false,
+                },
+                Instr::GetList(idx) => match self.deref(self.x[idx]) {
+                    Cell::Ref(addr) => {
+                        let list = Cell::Lis(self.heap.len());
+                        self.bind(addr, list);
+                        self.mode = Mode::Write;
+                        true
+                    }
+                    Cell::Lis(addr) => {
+                        self.s = addr;
+                        self.mode = Mode::Read;
+                        true
+                    }
+                    _ => false,
+                },
+                Instr::GetConstant(constant, idx) => self.unify_constant(self.x[idx], constant),
+                Instr::SetVariable(reg) => {
+                    let cell = self.new_var();
+                    self.set(reg, cell);
+                    true
+                }
+                Instr::SetValue(reg) => {
+                    let cell = self.get(reg);
+                    self.heap.push(cell);
+                    true
+                }
+                Instr::SetConstant(constant) => {
+                    self.heap.push(Cell::Con(constant));
+                    true
+                }
+                Instr::UnifyVariable(reg) => {
+                    match self.mode {
+                        Mode::Read => {
+                            self.set(reg, self.heap[self.s]);
+                            self.s += 1;
+                        }
+                        Mode::Write => {
+                            let cell = self.new_var();
+                            self.set(reg, cell);
+                        }
+                    }
+                    true
+                }
+                Instr::UnifyValue(reg) => match self.mode {
+                    Mode::Read => {
+                        self.s += 1;
+                        self.unify(self.get(reg), Cell::Ref(self.s - 1))
+                    }
+                    Mode::Write => {
+                        let cell = self.get(reg);
+                        self.heap.push(cell);
+                        true
+                    }
+                },
+                Instr::UnifyConstant(constant) => match self.mode {
+                    Mode::Read => {
+                        self.s += 1;
+                        self.unify_constant(Cell::Ref(self.s - 1), constant)
+                    }
+                    Mode::Write => {
+                        self.heap.push(Cell::Con(constant));
+                        true
+                    }
+                },
+                Instr::Allocate(size) => {
+                    self.env = Some(Rc::new(Env {
+                        prev: self.env.take(),
+                        cp: self.cp,
+                        ys: RefCell::new(vec![Cell::Ref(0); size]),
+                    }));
+                    true
+                }
+                Instr::Deallocate => {
+                    let env = self.env.take().unwrap();
+                    self.cp = env.cp;
+                    self.env = env.prev.clone();
+                    true
+                }
+                Instr::Call(functor) => {
+                    self.cp = self.p;
+                    self.jump(functor);
+                    true
+                }
+                Instr::Execute(functor) => {
+                    self.jump(functor);
+                    true
+                }
+                Instr::Proceed => {
+                    self.p = self.cp;
+                    true
+                }
+                Instr::TryMeElse(alt) => {
+                    self.push_choicepoint(alt);
+                    true
+                }
+                Instr::RetryMeElse(alt) => {
+                    self.restore();
+                    self.choicepoints.last_mut().unwrap().alt = alt;
+                    true
+                }
+                Instr::TrustMe => {
+                    self.restore();
+                    self.choicepoints.pop();
+                    true
+                }
+                Instr::GetLevel(reg) => {
+                    self.set(reg, Cell::Con(Const::Int(self.b0)));
+                    true
+                }
+                Instr::Cut(reg) => match self.deref(self.get(reg)) {
+                    Cell::Con(Const::Int(level)) => {
+                        self.choicepoints.truncate(level);
+                        true
+                    }
+                    _ => false,
+                },
+                Instr::Try(clause) => {
+                    self.push_choicepoint(self.p);
+                    self.p = clause;
+                    true
+                }
+                Instr::Retry(clause) => {
+                    self.restore();
+                    self.choicepoints.last_mut().unwrap().alt = self.p;
+                    self.p = clause;
+                    true
+                }
+                Instr::Trust(clause) => {
+                    self.restore();
+                    self.choicepoints.pop();
+                    self.p = clause;
+                    true
+                }
+                Instr::SwitchOnTerm {
+                    var,
+                    constant,
+                    list,
+                    structure,
+                } => {
+                    let label = match self.deref(self.x[0]) {
+                        Cell::Ref(_) => Some(var),
+                        Cell::Con(_) => constant,
+                        Cell::Lis(_) => list,
+                        Cell::Str(_) => structure,
+                        Cell::Fun(_) => unreachable!(),
+                    };
+                    self.goto(label)
+                }
+                Instr::SwitchOnConstant(table, default) => {
+                    let label = match self.deref(self.x[0]) {
+                        Cell::Con(constant) => self.program.constant_tables[table]
+                            .get(&constant)
+                            .copied()
+                            .or(default),
+                        _ => default,
+                    };
+                    self.goto(label)
+                }
+                Instr::SwitchOnStructure(table, default) => {
+                    let label = match self.deref(self.x[0]) {
+                        Cell::Str(addr) => match self.heap[addr] {
+                            Cell::Fun(functor) => self.program.structure_tables[table]
+                                .get(&functor)
+                                .copied()
+                                .or(default),
+                            _ => unreachable!(),
+                        },
+                        _ => default,
+                    };
+                    self.goto(label)
+                }
+                Instr::CallBuiltin(builtin) => self.call_builtin(builtin),
+                Instr::Fail => false,
+                Instr::Halt => return true,
+            };
+
+            if !ok && !self.backtrack() {
+                return false;
+            }
+        }
+    }
+
+    fn jump(&mut self, functor: Functor) {
+        self.num_args = functor.arity;
+        self.b0 = self.choicepoints.len();
+        self.p = self.program.procedures[&functor];
+    }
+
+    fn goto(&mut self, label: Option<usize>) -> bool {
+        match label {
+            Some(addr) => {
+                self.p = addr;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn push_choicepoint(&mut self, alt: usize) {
+        self.choicepoints.push(ChoicePoint {
+            args: self.x[..self.num_args].to_vec(),
+            env: self.env.clone(),
+            cp: self.cp,
+            b0: self.b0,
+            alt,
+            trail_len: self.trail.len(),
+            heap_len: self.heap.len(),
+        });
+    }
+
+    fn backtrack(&mut self) -> bool {
+        match self.choicepoints.last() {
+            Some(choicepoint) => {
+                self.p = choicepoint.alt;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn restore(&mut self) {
+        let choicepoint = self.choicepoints.last().unwrap();
+        self.x[..choicepoint.args.len()].copy_from_slice(&choicepoint.args);
+        self.num_args = choicepoint.args.len();
+        self.env = choicepoint.env.clone();
+        self.cp = choicepoint.cp;
+        self.b0 = choicepoint.b0;
+        for addr in self.trail.drain(choicepoint.trail_len..) {
+            self.heap[addr] = Cell::Ref(addr);
+        }
+        self.heap.truncate(choicepoint.heap_len);
+    }
+
+    fn heap_boundary(&self) -> usize {
+        self.choicepoints
+            .last()
+            .map(|choicepoint| choicepoint.heap_len)
+            .unwrap_or(0)
+    }
+
+    fn new_var(&mut self) -> Cell {
+        let cell = Cell::Ref(self.heap.len());
+        self.heap.push(cell);
+        cell
+    }
+
+    fn get(&self, reg: Reg) -> Cell {
+        match reg {
+            Reg::X(idx) => self.x[idx],
+            Reg::Y(idx) => self.env.as_ref().unwrap().ys.borrow()[idx],
+        }
+    }
+
+    fn set(&mut self, reg: Reg, cell: Cell) {
+        match reg {
+            Reg::X(idx) => self.x[idx] = cell,
+            Reg::Y(idx) => self.env.as_ref().unwrap().ys.borrow_mut()[idx] = cell,
+        }
+    }
+
+    fn deref(&self, mut cell: Cell) -> Cell {
+        while let Cell::Ref(addr) = cell {
+            if self.heap[addr] == cell {
+                break;
+            }
+            cell = self.heap[addr];
+        }
+        cell
+    }
+
+    fn bind(&mut self, addr: usize, cell: Cell) {
+        self.heap[addr] = cell;
+        if addr < self.heap_boundary() {
+            self.trail.push(addr);
+        }
+    }
+
+    fn unify_constant(&mut self, cell: Cell, constant: Const) -> bool {
+        match self.deref(cell) {
+            Cell::Ref(addr) => {
+                self.bind(addr, Cell::Con(constant));
+                true
+            }
+            Cell::Con(other) => other == constant,
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, left: Cell, right: Cell) -> bool {
+        let mut pending = vec![(left, right)];
+
+        while let Some((left, right)) = pending.pop() {
+            match (self.deref(left), self.deref(right)) {
+                (Cell::Ref(a), Cell::Ref(b)) => {
+                    if a < b {
+                        self.bind(b, Cell::Ref(a));
+                    } else if b < a {
+                        self.bind(a, Cell::Ref(b));
+                    }
+                }
+                (Cell::Ref(addr), cell) | (cell, Cell::Ref(addr)) => self.bind(addr, cell),
+                (Cell::Con(a), Cell::Con(b)) => {
+                    if a != b {
+                        return false;
+                    }
+                }
+                (Cell::Lis(a), Cell::Lis(b)) => {
+                    pending.push((Cell::Ref(a + 1), Cell::Ref(b + 1)));
+                    pending.push((Cell::Ref(a), Cell::Ref(b)));
+                }
+                (Cell::Str(a), Cell::Str(b)) => {
+                    let functor = match (self.heap[a], self.heap[b]) {
+                        (Cell::Fun(fa), Cell::Fun(fb)) if fa == fb => fa,
+                        _ => return false,
+                    };
+                    for idx in (1..=functor.arity).rev() {
+                        pending.push((Cell::Ref(a + idx), Cell::Ref(b + idx)));
+                    }
+                }
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    fn call_builtin(&mut self, builtin: Builtin) -> bool {
+        if builtin == Builtin::Unify {
+            return self.unify(self.x[0], self.x[1]);
+        }
+
+        let compare = |left: usize, right: usize| match builtin {
+            Builtin::GreaterThan => left > right,
+            Builtin::LessThan => left < right,
+            Builtin::GreaterThanEqual => left >= right,
+            Builtin::LessThanEqual => left <= right,
+            Builtin::Unify => unreachable!(),
+        };
+
+        match (self.deref(self.x[0]), self.deref(self.x[1])) {
+            (Cell::Con(Const::Int(left)), Cell::Con(Const::Int(right))) => compare(left, right),
+            (Cell::Ref(addr), Cell::Con(Const::Int(right))) => {
+                // TODO: use random number generator
+                let witness = match builtin {
+                    Builtin::GreaterThan => right.checked_add(1),
+                    Builtin::LessThan => right.checked_sub(1),
+                    _ => Some(right),
+                };
+                self.bind_witness(addr, witness)
+            }
+            (Cell::Con(Const::Int(left)), Cell::Ref(addr)) => {
+                let witness = match builtin {
+                    Builtin::GreaterThan => left.checked_sub(1),
+                    Builtin::LessThan => left.checked_add(1),
+                    _ => Some(left),
+                };
+                self.bind_witness(addr, witness)
+            }
+            _ => false,
+        }
+    }
+
+    fn bind_witness(&mut self, addr: usize, witness: Option<usize>) -> bool {
+        match witness {
+            Some(val) => {
+                self.bind(addr, Cell::Con(Const::Int(val)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn read(&self, cell: Cell) -> Term {
+        match self.deref(cell) {
+            Cell::Ref(addr) => Term::Variable(format!("_{}", addr)),
+            Cell::Con(Const::Atom(id)) => {
+                Term::CompoundTerm(self.program.atoms.name(id).to_owned(), vec![])
+            }
+            Cell::Con(Const::Str(id)) => Term::Str(self.program.atoms.name(id).to_owned()),
+            Cell::Con(Const::Int(val)) => Term::Int(val),
+            Cell::Con(Const::Nil) => Term::List(vec![]),
+            Cell::Str(addr) => match self.heap[addr] {
+                Cell::Fun(functor) => Term::CompoundTerm(
+                    self.program.atoms.name(functor.name).to_owned(),
+                    (1..=functor.arity)
+                        .map(|idx| self.read(Cell::Ref(addr + idx)))
+                        .collect(),
+                ),
+                _ => unreachable!(),
+            },
+            Cell::Lis(addr) => {
+                let mut values = vec![self.read(Cell::Ref(addr))];
+                let mut tail = self.deref(Cell::Ref(addr + 1));
+                while let Cell::Lis(next) = tail {
+                    values.push(self.read(Cell::Ref(next)));
+                    tail = self.deref(Cell::Ref(next + 1));
+                }
+                match tail {
+                    Cell::Con(Const::Nil) => Term::List(values),
+                    _ => values
+                        .into_iter()
+                        .rev()
+                        .fold(self.read(tail), |tail, head| {
+                            Term::Cons(Box::new(head), Box::new(tail))
+                        }),
+                }
+            }
+            Cell::Fun(_) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::DB, lexer, parser::Parser};
+
+    const PROGRAM: &str = "
+        p(1). p(2). p(3).
+        first(?x) :- p(?x), !.
+        alt(?x) :- (?x = 1 ; ?x = 2).
+        max(?x, ?y, ?x) :- ?x >= ?y, !.
+        max(?x, ?y, ?y).
+        size(?x, ?c) :- (?x > 2 -> ?c = big ; ?x > 1 -> ?c = mid ; ?c = small).
+        absent(?x) :- \\+ p(?x).
+        cut_branch(?x, ?y) :- p(?x), (?x > 1, ! ; ?x = 1), p(?y).
+        largest(?x) :- p(?x), \\+ (p(?y), ?y > ?x).
+        some(?x) :- p(?x) -> true ; ?x = none.
+        member(?x, [?x|?t]).
+        member(?x, [?h|?t]) :- member(?x, ?t).
+        append([], ?l, ?l).
+        append([?h|?t], ?l, [?h|?r]) :- append(?t, ?l, ?r).
+    ";
+
+    fn parse(source: &str) -> Vec<Item> {
+        Parser::new(lexer::tokenize(source.to_string()).unwrap())
+            .parse()
+            .unwrap()
+    }
+
+    fn query(source: &str) -> Term {
+        Parser::new(lexer::tokenize(source.to_string()).unwrap())
+            .parse_query()
+            .unwrap()
+    }
+
+    fn render(answers: Vec<HashMap<String, Term>>) -> Vec<String> {
+        answers
+            .into_iter()
+            .map(|answer| {
+                let mut bindings: Vec<String> = answer
+                    .into_iter()
+                    .map(|(var, value)| format!("{var}={value}"))
+                    .collect();
+                bindings.sort();
+                bindings.join(" ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let mut machine = Machine::new(parse(PROGRAM)).unwrap();
+        let db = DB::new(parse(PROGRAM));
+        for source in [
+            "first(?x).",
+            "alt(?x).",
+            "max(3, 5, ?m).",
+            "max(5, 3, ?m).",
+            "size(3, ?c).",
+            "size(2, ?c).",
+            "size(1, ?c).",
+            "absent(4).",
+            "absent(1).",
+            "cut_branch(?x, ?y).",
+            "largest(?x).",
+            "some(?x).",
+            "p(?x), !.",
+            "(p(?x) ; ?x = 4).",
+            "(p(?x), ?x > 1 -> ?y = yes ; ?y = no).",
+            "member(?x, [a, b, c]).",
+            "append(?x, ?y, [1, 2, 3]).",
+            "append([1], [2, 3], ?z), member(2, ?z).",
+        ] {
+            let query = query(source);
+            assert_eq!(
+                render(machine.query_all(&query).unwrap()),
+                render(db.query_all(&query).unwrap()),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_goals() {
+        assert!(matches!(
+            Machine::new(parse("p(?x) :- q(?x).")),
+            Err(Error::Existence(name, 1)) if name == "q"
+        ));
+        assert!(matches!(
+            Machine::new(parse("p(?x) :- ?x.")),
+            Err(Error::Instantiation)
+        ));
+        assert!(matches!(
+            Machine::new(parse("p :- 1.")),
+            Err(Error::Type(..))
+        ));
+        let mut machine = Machine::new(parse("p(1).")).unwrap();
+        assert!(matches!(
+            machine.query_all(&query("q(?x).")),
+            Err(Error::Existence(..))
+        ));
+        assert!(matches!(
+            machine.query_all(&query("p(?x), ?g.")),
+            Err(Error::Instantiation)
+        ));
+        assert_eq!(machine.query_all(&query("p(?x).")).unwrap().len(), 1);
+    }
+}